@@ -0,0 +1,89 @@
+#[macro_use]
+extern crate criterion;
+extern crate bulletproofs;
+extern crate curve25519_dalek;
+extern crate merlin;
+extern crate rand;
+extern crate zkvm;
+
+use bulletproofs::r1cs::Prover;
+use criterion::Criterion;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+
+use zkvm::{Entry, Predicate, Signature, Tx, TxID, VerificationKey, VM};
+
+const BATCH_SIZE: usize = 64;
+
+/// Makes a minimal valid transaction: `<pubkey> nonce signtx`.
+fn make_tx() -> Tx {
+    let privkey = Scalar::random(&mut rand::thread_rng());
+    let pubkey = VerificationKey::from_secret(&privkey);
+    let (version, mintime, maxtime) = (1u64, 0u64, 0u64);
+
+    let mut program = vec![0x00]; // push:32
+    program.extend_from_slice(&[32, 0, 0, 0]);
+    program.extend_from_slice(pubkey.0.as_bytes());
+    program.push(0x1f); // nonce
+    program.push(0x21); // signtx
+
+    let txid = TxID::from_log(&[
+        Entry::Header(version, mintime, maxtime),
+        Entry::Nonce(Predicate(pubkey.0), maxtime),
+    ]);
+    let mut signtx_transcript = Transcript::new(b"ZkVM.signtx");
+    signtx_transcript.commit_bytes(b"txid", &txid.0);
+    let signature = Signature::sign_single(&mut signtx_transcript, privkey);
+
+    let bp_gens = zkvm::bulletproof_gens(0);
+    let mut r1cs_transcript = Transcript::new(b"ZkVM.r1cs");
+    let proof = Prover::new(&bp_gens, zkvm::pedersen_gens(), &mut r1cs_transcript)
+        .prove()
+        .unwrap();
+
+    Tx {
+        version,
+        mintime,
+        maxtime,
+        program,
+        signature,
+        proof,
+    }
+}
+
+fn make_txs() -> Vec<Tx> {
+    (0..BATCH_SIZE).map(|_| make_tx()).collect()
+}
+
+fn verify_one_by_one(c: &mut Criterion) {
+    let txs = make_txs();
+    c.bench_function("verify_tx for each tx", move |b| {
+        b.iter(|| {
+            for tx in txs.iter() {
+                VM::verify_tx(tx).unwrap();
+            }
+        })
+    });
+}
+
+fn verify_sequential_batch(c: &mut Criterion) {
+    let txs = make_txs();
+    c.bench_function("verify_txs", move |b| {
+        b.iter(|| VM::verify_txs(&txs).unwrap())
+    });
+}
+
+fn verify_parallel_batch(c: &mut Criterion) {
+    let txs = make_txs();
+    c.bench_function("verify_txs_parallel", move |b| {
+        b.iter(|| VM::verify_txs_parallel(&txs).unwrap())
+    });
+}
+
+criterion_group!(
+    verify,
+    verify_one_by_one,
+    verify_sequential_batch,
+    verify_parallel_batch
+);
+criterion_main!(verify);
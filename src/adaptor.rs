@@ -0,0 +1,161 @@
+//! Adaptor signatures for atomic swaps.
+//!
+//! A pre-signature is made against an adaptor point `T = t·B` and can be verified without knowing `t`.
+//! Completing it with `t` produces a normal `Signature` that verifies with `Signature::verify_single`,
+//! and anyone who sees both the pre-signature and the completed signature learns `t`.
+#![allow(non_snake_case)]
+
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+
+use crate::errors::VMError;
+use crate::gens;
+use crate::point_ops::PointOp;
+use crate::signature::{self, Signature, VerificationKey};
+use crate::transcript::TranscriptProtocol;
+
+/// Signature that becomes valid only after it is completed with the secret of the adaptor point.
+#[derive(Copy, Clone, Debug)]
+pub struct AdaptorSignature {
+    /// Nonce commitment of the completed signature, including the adaptor point.
+    R: CompressedRistretto,
+    s: Scalar,
+}
+
+impl AdaptorSignature {
+    /// Creates a pre-signature for a single private key against the adaptor point `T = t·B`.
+    /// The transcript must be in the same state as the one passed to `Signature::verify_single`.
+    /// Fails if the adaptor is not a valid point.
+    pub fn sign(
+        transcript: &mut Transcript,
+        privkey: Scalar,
+        adaptor: VerificationKey,
+    ) -> Result<Self, VMError> {
+        let gens = gens::pedersen_gens();
+        let T = adaptor.0.decompress().ok_or(VMError::InvalidPoint)?;
+        let pubkey = VerificationKey::from_secret(&privkey);
+        let x = signature::key_weights(transcript, &[pubkey])[0];
+
+        // Generate secret nonce
+        let mut rng = transcript
+            .build_rng()
+            .commit_witness_bytes(b"privkey", privkey.as_bytes())
+            .finalize(&mut rand::thread_rng());
+        let r = Scalar::random(&mut rng);
+
+        // The completed signature commits to the nonce offset by the adaptor point.
+        let R = (r * gens.B + T).compress();
+        transcript.commit_point(b"R", &R);
+        let e = transcript.challenge_scalar(b"e");
+
+        Ok(AdaptorSignature {
+            R,
+            s: r + e * x * privkey,
+        })
+    }
+
+    /// Verifies the pre-signature for the key `pubkey` and the adaptor point.
+    /// Returns a `PointOp` instance that can be verified in a batch with other operations.
+    pub fn verify(
+        &self,
+        transcript: &mut Transcript,
+        pubkey: VerificationKey,
+        adaptor: VerificationKey,
+    ) -> PointOp {
+        let x = signature::key_weights(transcript, &[pubkey])[0];
+        transcript.commit_point(b"R", &self.R);
+        let e = transcript.challenge_scalar(b"e");
+
+        // `s*B == e*x*P + R - T`
+        //      ->
+        // `0 == -s*B + e*x*P + R - T`
+        PointOp {
+            primary: Some(-self.s),
+            secondary: None,
+            arbitrary: vec![
+                (e * x, pubkey.0),
+                (Scalar::one(), self.R),
+                (-Scalar::one(), adaptor.0),
+            ],
+        }
+    }
+
+    /// Completes the pre-signature with the secret `t` of the adaptor point.
+    pub fn complete(&self, secret: Scalar) -> Signature {
+        Signature {
+            R: self.R,
+            s: self.s + secret,
+        }
+    }
+
+    /// Extracts the secret of the adaptor point from the completed signature.
+    /// Returns `None` if the signature was not completed from this pre-signature.
+    pub fn extract(&self, signature: &Signature) -> Option<Scalar> {
+        if signature.R != self.R {
+            return None;
+        }
+        Some(signature.s - self.s)
+    }
+}
+
+// Serialization
+impl AdaptorSignature {
+    /// Decodes a pre-signature from 64-byte array.
+    pub fn from_bytes(sig: [u8; 64]) -> Result<Self, VMError> {
+        let sig = Signature::from_bytes(sig)?;
+        Ok(AdaptorSignature { R: sig.R, s: sig.s })
+    }
+
+    /// Encodes the pre-signature as a 64-byte array.
+    pub fn to_bytes(&self) -> [u8; 64] {
+        Signature {
+            R: self.R,
+            s: self.s,
+        }
+        .to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adaptor_signature() {
+        let privkey = Scalar::random(&mut rand::thread_rng());
+        let pubkey = VerificationKey::from_secret(&privkey);
+        let secret = Scalar::random(&mut rand::thread_rng());
+        let adaptor = VerificationKey::from_secret(&secret);
+
+        let presig =
+            AdaptorSignature::sign(&mut Transcript::new(b"swap"), privkey, adaptor).unwrap();
+        let presig = AdaptorSignature::from_bytes(presig.to_bytes()).unwrap();
+        assert!(presig
+            .verify(&mut Transcript::new(b"swap"), pubkey, adaptor)
+            .verify()
+            .is_ok());
+        let wrong_adaptor = VerificationKey::from_secret(&Scalar::one());
+        assert!(presig
+            .verify(&mut Transcript::new(b"swap"), pubkey, wrong_adaptor)
+            .verify()
+            .is_err());
+
+        // Pre-signature alone is not a valid signature.
+        let incomplete = presig.complete(Scalar::zero());
+        assert!(incomplete
+            .verify_single(&mut Transcript::new(b"swap"), pubkey)
+            .verify()
+            .is_err());
+
+        let sig = presig.complete(secret);
+        assert!(sig
+            .verify_single(&mut Transcript::new(b"swap"), pubkey)
+            .verify()
+            .is_ok());
+        assert_eq!(presig.extract(&sig), Some(secret));
+
+        let other = Signature::sign_single(&mut Transcript::new(b"swap"), privkey);
+        assert_eq!(presig.extract(&other), None);
+    }
+}
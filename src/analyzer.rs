@@ -0,0 +1,511 @@
+//! Static analysis of the stack effects of a program.
+//!
+//! ZkVM has no jumps and all instructions have immediate sizes,
+//! so the types of the stack items can be inferred before the program is executed.
+//! The analyzer detects failures that are guaranteed to happen regardless of the data
+//! (type mismatches, stack underflow, unclean stack) and reports linear items left unconsumed.
+//!
+//! Payloads of contracts loaded via `input` and effects of programs run via `call` or `delegate`
+//! are not known statically: once they are placed on the stack, the items beneath them are no longer tracked.
+//! Extension opcodes are treated as failures, as in the transactions of the current version.
+use crate::errors::VMError;
+use crate::ops::Instruction;
+
+/// Type of a stack item.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ItemType {
+    /// Data string.
+    Data,
+    /// Contract.
+    Contract,
+    /// Value.
+    Value,
+    /// Wide value.
+    WideValue,
+    /// Variable.
+    Variable,
+    /// Expression.
+    Expression,
+    /// Constraint.
+    Constraint,
+}
+
+/// Result of the static analysis of a program.
+#[derive(Clone, Debug)]
+pub struct Analysis {
+    /// Inferred stack after each analyzed instruction.
+    pub steps: Vec<AnalysisStep>,
+
+    /// Failure guaranteed to happen at a given offset (`None` if it happens after the last instruction).
+    /// The analysis stops at the first such failure.
+    pub failure: Option<(Option<usize>, VMError)>,
+
+    /// Linear items (contracts, values and wide values) remaining on the stack at the end of the program.
+    pub unconsumed: Vec<UnconsumedItem>,
+}
+
+/// Inferred state of the stack after an instruction.
+#[derive(Clone, Debug)]
+pub struct AnalysisStep {
+    /// Byte offset of the instruction.
+    pub offset: usize,
+
+    /// Analyzed instruction.
+    pub instruction: Instruction,
+
+    /// Types of the tracked items from the bottom to the top; `None` for items of unknown type.
+    pub stack: Vec<Option<ItemType>>,
+
+    /// Whether the tracked items are the entire stack.
+    pub complete: bool,
+}
+
+/// Linear item left on the stack.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UnconsumedItem {
+    /// Type of the item.
+    pub item: ItemType,
+
+    /// Byte offset of the instruction that placed the item on the stack.
+    pub offset: usize,
+}
+
+/// Analyzes the stack effects of the transaction program.
+pub fn analyze(program: &[u8]) -> Analysis {
+    let mut state = State {
+        stack: Vec::new(),
+        complete: true,
+        offset: 0,
+    };
+    let mut analysis = Analysis {
+        steps: Vec::new(),
+        failure: None,
+        unconsumed: Vec::new(),
+    };
+    let mut unique = false;
+    let mut opaque = false;
+
+    let mut offset = 0;
+    while offset < program.len() {
+        let (instruction, size) = match Instruction::parse(&program[offset..]) {
+            Some((instr, size)) if size <= program.len() - offset => (instr, size),
+            _ => {
+                analysis.failure = Some((Some(offset), VMError::FormatError));
+                return analysis;
+            }
+        };
+        state.offset = offset;
+        match instruction {
+            Instruction::Input | Instruction::Nonce => unique = true,
+            Instruction::Call | Instruction::Delegate => opaque = true,
+            _ => {}
+        }
+        if let Err(err) = state.step(instruction) {
+            analysis.failure = Some((Some(offset), err));
+            return analysis;
+        }
+        analysis.steps.push(AnalysisStep {
+            offset,
+            instruction,
+            stack: state.stack.iter().map(|item| item.ty).collect(),
+            complete: state.complete,
+        });
+        offset += size;
+    }
+
+    analysis.unconsumed = state
+        .stack
+        .iter()
+        .filter_map(|item| match item.ty {
+            Some(ty @ ItemType::Contract)
+            | Some(ty @ ItemType::Value)
+            | Some(ty @ ItemType::WideValue) => Some(UnconsumedItem {
+                item: ty,
+                offset: item.offset,
+            }),
+            _ => None,
+        })
+        .collect();
+
+    // Programs run by `call` or `delegate` may consume the remaining items or make the txid unique.
+    if !opaque {
+        if state.stack.len() > 0 {
+            analysis.failure = Some((None, VMError::StackNotClean));
+        } else if !unique {
+            analysis.failure = Some((None, VMError::NotUniqueTxid));
+        }
+    }
+    analysis
+}
+
+/// Stack item with a statically known or unknown type.
+#[derive(Clone, Debug)]
+struct AbstractItem {
+    ty: Option<ItemType>,
+
+    /// Payload of a contract, if known.
+    payload: Option<Vec<AbstractItem>>,
+
+    /// Offset of the instruction that placed the item on the stack.
+    offset: usize,
+}
+
+struct State {
+    /// Tracked items at the top of the stack.
+    stack: Vec<AbstractItem>,
+
+    /// False if there may be untracked items below the tracked ones.
+    complete: bool,
+
+    /// Offset of the current instruction.
+    offset: usize,
+}
+
+impl State {
+    fn step(&mut self, instruction: Instruction) -> Result<(), VMError> {
+        use self::ItemType::*;
+        match instruction {
+            Instruction::Push(_) => self.push(Data),
+            Instruction::Drop => {
+                self.pop_copyable()?;
+            }
+            Instruction::Dup(i) => {
+                if i >= self.stack.len() {
+                    self.check_depth(i)?;
+                    self.push_unknown();
+                } else {
+                    let item = self.stack[self.stack.len() - i - 1].clone();
+                    check_copyable(&item)?;
+                    self.stack.push(item);
+                }
+            }
+            Instruction::Roll(i) => {
+                if i >= self.stack.len() {
+                    self.check_depth(i)?;
+                    self.push_unknown();
+                } else {
+                    let item = self.stack.remove(self.stack.len() - i - 1);
+                    self.stack.push(item);
+                }
+            }
+            Instruction::Const => self.transform(&[Data], &[Expression])?,
+            Instruction::Var => self.transform(&[Data], &[Variable])?,
+            Instruction::Alloc | Instruction::Mintime | Instruction::Maxtime => {
+                self.push(Expression)
+            }
+            Instruction::Neg | Instruction::Range(_) => {
+                self.pop_expression()?;
+                self.push(Expression);
+            }
+            Instruction::Add | Instruction::Mul => {
+                self.pop_expression()?;
+                self.pop_expression()?;
+                self.push(Expression);
+            }
+            Instruction::Eq => {
+                self.pop_expression()?;
+                self.pop_expression()?;
+                self.push(Constraint);
+            }
+            Instruction::And | Instruction::Or => {
+                self.transform(&[Constraint, Constraint], &[Constraint])?
+            }
+            Instruction::Verify => self.transform(&[Constraint], &[])?,
+            Instruction::Blind => {
+                self.pop(Data)?;
+                self.pop_expression()?;
+                self.pop(Data)?;
+                self.pop(Data)?;
+                self.push(Variable);
+            }
+            Instruction::Reblind => self.transform(&[Variable, Data, Data], &[Variable])?,
+            Instruction::Unblind => {
+                self.pop_expression()?;
+                self.pop(Data)?;
+                self.pop(Data)?;
+                self.push(Variable);
+            }
+            Instruction::Issue => {
+                self.pop(Data)?;
+                self.pop(Variable)?;
+                self.pop(Variable)?;
+                let value = self.item(Value);
+                self.push_contract(Some(vec![value]));
+            }
+            Instruction::Borrow => self.transform(&[Variable, Variable], &[WideValue, Value])?,
+            Instruction::Retire => self.transform(&[Value], &[])?,
+            Instruction::Qty | Instruction::Flavor => {
+                let value = self.pop(Value)?;
+                self.stack.push(value);
+                self.push(Variable);
+            }
+            Instruction::Cloak(m, n) => {
+                for _ in 0..2 * n {
+                    self.pop(Data)?;
+                }
+                for _ in 0..m {
+                    let item = self.pop_any()?;
+                    match item.ty {
+                        Some(Value) | Some(WideValue) | None => {}
+                        _ => return Err(VMError::TypeNotWideValue),
+                    }
+                }
+                for _ in 0..n {
+                    self.push(Value);
+                }
+            }
+            Instruction::Import => self.transform(&[Variable, Variable, Data], &[Value])?,
+            Instruction::Export => self.transform(&[Value, Data], &[])?,
+            Instruction::Input => {
+                self.pop(Data)?;
+                self.push_contract(None);
+            }
+            Instruction::Output(k) => {
+                self.pop(Data)?;
+                self.pop_portable(k)?;
+            }
+            Instruction::Contract(k) => {
+                self.pop(Data)?;
+                let payload = self.pop_portable(k)?;
+                self.push_contract(payload);
+            }
+            Instruction::Nonce => {
+                self.pop(Data)?;
+                self.push_contract(Some(Vec::new()));
+            }
+            Instruction::Log => self.transform(&[Data], &[])?,
+            Instruction::Signtx => {
+                let contract = self.pop(Contract)?;
+                self.unwrap_payload(contract);
+            }
+            Instruction::Call => {
+                self.pop(Data)?;
+                self.pop(Contract)?;
+                self.forget();
+            }
+            Instruction::Delegate => {
+                self.pop(Data)?;
+                self.pop(Data)?;
+                self.pop(Contract)?;
+                self.forget();
+            }
+            Instruction::Left | Instruction::Right => {
+                self.pop(Data)?;
+                self.pop(Data)?;
+                let contract = self.pop(Contract)?;
+                self.push_contract(contract.payload);
+            }
+            Instruction::Ext(_) => return Err(VMError::ExtensionsNotAllowed),
+        }
+        Ok(())
+    }
+
+    /// Pops items of the given types (top first) and pushes items of the given types.
+    fn transform(&mut self, inputs: &[ItemType], outputs: &[ItemType]) -> Result<(), VMError> {
+        for ty in inputs.iter() {
+            self.pop(*ty)?;
+        }
+        for ty in outputs.iter() {
+            self.push(*ty);
+        }
+        Ok(())
+    }
+
+    fn item(&self, ty: ItemType) -> AbstractItem {
+        AbstractItem {
+            ty: Some(ty),
+            payload: None,
+            offset: self.offset,
+        }
+    }
+
+    fn push(&mut self, ty: ItemType) {
+        let item = self.item(ty);
+        self.stack.push(item);
+    }
+
+    fn push_unknown(&mut self) {
+        self.stack.push(AbstractItem {
+            ty: None,
+            payload: None,
+            offset: self.offset,
+        });
+    }
+
+    fn push_contract(&mut self, payload: Option<Vec<AbstractItem>>) {
+        let mut item = self.item(ItemType::Contract);
+        item.payload = payload;
+        self.stack.push(item);
+    }
+
+    /// Places the contract payload on the stack.
+    fn unwrap_payload(&mut self, contract: AbstractItem) {
+        match contract.payload {
+            Some(payload) => self.stack.extend(payload),
+            None => self.forget(),
+        }
+    }
+
+    /// Stops tracking the items when an unknown number of items is placed on the stack.
+    fn forget(&mut self) {
+        self.stack.clear();
+        self.complete = false;
+    }
+
+    fn check_depth(&self, i: usize) -> Result<(), VMError> {
+        if self.complete && i >= self.stack.len() {
+            return Err(VMError::StackUnderflow);
+        }
+        Ok(())
+    }
+
+    fn pop_any(&mut self) -> Result<AbstractItem, VMError> {
+        match self.stack.pop() {
+            Some(item) => Ok(item),
+            None if self.complete => Err(VMError::StackUnderflow),
+            None => Ok(AbstractItem {
+                ty: None,
+                payload: None,
+                offset: self.offset,
+            }),
+        }
+    }
+
+    fn pop(&mut self, ty: ItemType) -> Result<AbstractItem, VMError> {
+        let item = self.pop_any()?;
+        match item.ty {
+            Some(t) if t != ty => Err(type_error(ty)),
+            _ => Ok(item),
+        }
+    }
+
+    fn pop_expression(&mut self) -> Result<(), VMError> {
+        match self.pop_any()?.ty {
+            Some(ItemType::Expression) | Some(ItemType::Variable) | None => Ok(()),
+            _ => Err(VMError::TypeNotExpression),
+        }
+    }
+
+    fn pop_copyable(&mut self) -> Result<(), VMError> {
+        let item = self.pop_any()?;
+        check_copyable(&item)
+    }
+
+    /// Pops `k` portable items and returns them in the stack order if all of them are tracked.
+    fn pop_portable(&mut self, k: usize) -> Result<Option<Vec<AbstractItem>>, VMError> {
+        let mut items = Vec::with_capacity(k);
+        let mut tracked = true;
+        for _ in 0..k {
+            let item = self.pop_any()?;
+            match item.ty {
+                Some(ItemType::Data) | Some(ItemType::Value) => items.insert(0, item),
+                None => tracked = false,
+                _ => return Err(VMError::TypeNotPortable),
+            }
+        }
+        if tracked {
+            Ok(Some(items))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn check_copyable(item: &AbstractItem) -> Result<(), VMError> {
+    match item.ty {
+        Some(ItemType::Contract) | Some(ItemType::Value) | Some(ItemType::WideValue) => {
+            Err(VMError::TypeNotCopyable)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn type_error(expected: ItemType) -> VMError {
+    match expected {
+        ItemType::Data => VMError::TypeNotData,
+        ItemType::Contract => VMError::TypeNotContract,
+        ItemType::Value => VMError::TypeNotValue,
+        ItemType::WideValue => VMError::TypeNotWideValue,
+        ItemType::Variable => VMError::TypeNotVariable,
+        ItemType::Expression => VMError::TypeNotExpression,
+        ItemType::Constraint => VMError::TypeNotConstraint,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn analyze_source(source: &str) -> Analysis {
+        analyze(&assemble(source).unwrap())
+    }
+
+    #[test]
+    fn valid_program() {
+        let analysis = analyze_source("0x00 nonce signtx");
+        assert_eq!(analysis.failure, None);
+        assert_eq!(analysis.unconsumed, vec![]);
+        assert_eq!(analysis.steps[1].stack, vec![Some(ItemType::Contract)]);
+        assert!(analysis.steps[2].complete);
+    }
+
+    #[test]
+    fn guaranteed_failures() {
+        let analysis = analyze_source("0x00 var 0x00 var 0x00 issue signtx drop");
+        assert_eq!(analysis.failure, Some((Some(22), VMError::TypeNotCopyable)));
+
+        let analysis = analyze_source("0x00 nonce signtx drop");
+        assert_eq!(analysis.failure, Some((Some(8), VMError::StackUnderflow)));
+
+        let analysis = analyze_source("0x00 0x00 output:0 0x00 retire");
+        assert_eq!(analysis.failure, Some((Some(23), VMError::TypeNotValue)));
+
+        let analysis = analyze_source("0x00 0x00 drop drop");
+        assert_eq!(analysis.failure, Some((None, VMError::NotUniqueTxid)));
+
+        assert_eq!(
+            analyze(&[0x00, 0x05, 0x00]).failure,
+            Some((Some(0), VMError::FormatError))
+        );
+    }
+
+    #[test]
+    fn unconsumed_items() {
+        let analysis = analyze_source("0x00 var 0x00 var 0x00 issue signtx 0x00 nonce");
+        assert_eq!(analysis.failure, Some((None, VMError::StackNotClean)));
+        assert_eq!(
+            analysis.unconsumed,
+            vec![
+                UnconsumedItem {
+                    item: ItemType::Value,
+                    offset: 20
+                },
+                UnconsumedItem {
+                    item: ItemType::Contract,
+                    offset: 28
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_payload() {
+        // The payload of the input contract is unknown, so the items below are no longer tracked.
+        let analysis = analyze_source("0x00 nonce 0x00 input signtx drop drop output:2");
+        assert_eq!(analysis.failure, None);
+        assert!(!analysis.steps.last().unwrap().complete);
+
+        // Unknown item in the middle of the payload is popped together with the items above it.
+        let analysis = analyze_source("0x00 input signtx 0x00 roll:1 0x00 output:2");
+        assert_eq!(analysis.failure, None);
+        assert_eq!(analysis.unconsumed, vec![]);
+        assert_eq!(analysis.steps.last().unwrap().stack, vec![]);
+
+        let analysis = analyze_source("0x00 input signtx 0x00 roll:1 0x00 contract:2");
+        assert_eq!(
+            analysis.steps.last().unwrap().stack,
+            vec![Some(ItemType::Contract)]
+        );
+    }
+}
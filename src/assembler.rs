@@ -0,0 +1,289 @@
+//! Assembler for the textual notation of ZkVM programs used in the specification.
+//!
+//! Syntax:
+//! - instructions are written by name with immediate data after colons:
+//!   `drop`, `dup:1`, `range:64`, `cloak:3:2`, `output:1`;
+//!   immediates are decimal or `0x`-prefixed hexadecimal numbers;
+//! - `ext:n` encodes an extension opcode `n`;
+//! - `push:n:x` pushes the hex-encoded data string `x` of `n` bytes, as written in the specification;
+//! - `0x...` pushes a hex-encoded data string, as a shorthand for `push`;
+//! - `base64:...` pushes a base64-encoded data string;
+//! - `<name>` pushes a data string provided by the caller under `name`;
+//! - `raw:x` inserts the hex-encoded bytes `x` as they are, e.g. an incomplete trailing instruction;
+//! - `#` starts a comment till the end of the line.
+use std::collections::HashMap;
+
+use crate::errors::AsmError;
+use crate::ops::{Instruction, Opcode};
+
+/// Assembles a program that does not refer to named data strings.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with(source, &HashMap::new())
+}
+
+/// Assembles a program, resolving `<name>` items with the provided data strings.
+pub fn assemble_with(
+    source: &str,
+    bindings: &HashMap<String, Vec<u8>>,
+) -> Result<Vec<u8>, AsmError> {
+    let mut program = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let code = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        for (start, token) in tokens(code) {
+            assemble_token(token, bindings, &mut program).map_err(|message| AsmError {
+                line: line_index + 1,
+                column: code[..start].chars().count() + 1,
+                message,
+            })?;
+        }
+    }
+    Ok(program)
+}
+
+/// Splits the line into whitespace-separated tokens with their byte offsets.
+fn tokens(code: &str) -> Vec<(usize, &str)> {
+    let mut result = Vec::new();
+    let mut start = None;
+    for (i, ch) in code.char_indices() {
+        match (ch.is_whitespace(), start) {
+            (true, Some(s)) => {
+                result.push((s, &code[s..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        result.push((s, &code[s..]));
+    }
+    result
+}
+
+fn assemble_token(
+    token: &str,
+    bindings: &HashMap<String, Vec<u8>>,
+    program: &mut Vec<u8>,
+) -> Result<(), String> {
+    if token.starts_with('<') {
+        if !token.ends_with('>') || token.len() < 3 {
+            return Err(format!("Malformed data placeholder `{}`", token));
+        }
+        let name = &token[1..token.len() - 1];
+        let data = bindings
+            .get(name)
+            .ok_or_else(|| format!("No data provided for `{}`", token))?;
+        return push_data(data, program);
+    }
+    if token.starts_with("0x") {
+        let data =
+            hex::decode(&token[2..]).map_err(|_| format!("Invalid hex string `{}`", token))?;
+        return push_data(&data, program);
+    }
+    if token.starts_with("raw:") {
+        let bytes =
+            hex::decode(&token[4..]).map_err(|_| format!("Invalid hex string `{}`", token))?;
+        program.extend_from_slice(&bytes);
+        return Ok(());
+    }
+    if token.starts_with("base64:") {
+        let data = base64::decode(&token[7..])
+            .map_err(|_| format!("Invalid base64 string `{}`", token))?;
+        return push_data(&data, program);
+    }
+
+    let mut parts = token.split(':');
+    let name = parts.next().unwrap_or("");
+    let immediates = parts.collect::<Vec<_>>();
+
+    let instruction = if name == "ext" {
+        let code = parse_immediates(name, &immediates, 1)?[0];
+        if code > 0xff || Opcode::from_u8(code as u8).is_some() {
+            return Err(format!("`{}` is not an extension opcode", code));
+        }
+        Instruction::Ext(code as u8)
+    } else {
+        let opcode =
+            Opcode::from_name(name).ok_or_else(|| format!("Unknown instruction `{}`", name))?;
+        match opcode {
+            Opcode::Push => {
+                if immediates.len() != 2 {
+                    return Err(format!(
+                        "`push` expects a length and a hex string, got `{}`",
+                        token
+                    ));
+                }
+                let len = parse_immediates(name, &immediates[..1], 1)?[0] as usize;
+                let hex_data = if immediates[1].starts_with("0x") {
+                    &immediates[1][2..]
+                } else {
+                    immediates[1]
+                };
+                let data = hex::decode(hex_data)
+                    .map_err(|_| format!("Invalid hex string `{}`", immediates[1]))?;
+                if data.len() != len {
+                    return Err(format!(
+                        "`push` declares {} bytes, but the string has {}",
+                        len,
+                        data.len()
+                    ));
+                }
+                return push_data(&data, program);
+            }
+            Opcode::Dup => Instruction::Dup(parse_immediates(name, &immediates, 1)?[0] as usize),
+            Opcode::Roll => Instruction::Roll(parse_immediates(name, &immediates, 1)?[0] as usize),
+            Opcode::Range => {
+                let n = parse_immediates(name, &immediates, 1)?[0];
+                if n > 0xff {
+                    return Err(format!("Range bitwidth `{}` is too large", n));
+                }
+                Instruction::Range(n as u8)
+            }
+            Opcode::Cloak => {
+                let mn = parse_immediates(name, &immediates, 2)?;
+                Instruction::Cloak(mn[0] as usize, mn[1] as usize)
+            }
+            Opcode::Output => {
+                Instruction::Output(parse_immediates(name, &immediates, 1)?[0] as usize)
+            }
+            Opcode::Contract => {
+                Instruction::Contract(parse_immediates(name, &immediates, 1)?[0] as usize)
+            }
+            _ => {
+                parse_immediates(name, &immediates, 0)?;
+                // Opcode without immediate data is a complete instruction by itself.
+                let (instruction, _) = Instruction::parse(&[opcode.to_u8()])
+                    .ok_or_else(|| format!("Cannot encode `{}`", name))?;
+                instruction
+            }
+        }
+    };
+    instruction.encode(program);
+    Ok(())
+}
+
+fn push_data(data: &[u8], program: &mut Vec<u8>) -> Result<(), String> {
+    if data.len() > u32::max_value() as usize {
+        return Err("Data string is too long".to_string());
+    }
+    Instruction::Push(data.len()).encode(program);
+    program.extend_from_slice(data);
+    Ok(())
+}
+
+/// Parses exactly `count` immediates as LE32 integers.
+fn parse_immediates(name: &str, immediates: &[&str], count: usize) -> Result<Vec<u32>, String> {
+    if immediates.len() != count {
+        return Err(format!(
+            "`{}` expects {} immediate argument(s), got {}",
+            name,
+            count,
+            immediates.len()
+        ));
+    }
+    immediates
+        .iter()
+        .map(|imm| {
+            let parsed = if imm.starts_with("0x") {
+                u32::from_str_radix(&imm[2..], 16)
+            } else {
+                imm.parse::<u32>()
+            };
+            parsed.map_err(|_| format!("Invalid immediate `{}` for `{}`", imm, name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(mut program: &[u8]) -> Vec<Instruction> {
+        let mut result = Vec::new();
+        while program.len() > 0 {
+            let (instr, size) = Instruction::parse(program).unwrap();
+            result.push(instr);
+            program = &program[size..];
+        }
+        result
+    }
+
+    #[test]
+    fn instructions_with_immediates() {
+        let program =
+            assemble("drop dup:1 roll:0x10 range:64 cloak:3:2 output:1 contract:0 ext:200")
+                .unwrap();
+        assert_eq!(
+            parse_all(&program),
+            vec![
+                Instruction::Drop,
+                Instruction::Dup(1),
+                Instruction::Roll(16),
+                Instruction::Range(64),
+                Instruction::Cloak(3, 2),
+                Instruction::Output(1),
+                Instruction::Contract(0),
+                Instruction::Ext(200),
+            ]
+        );
+    }
+
+    #[test]
+    fn data_literals() {
+        let mut bindings = HashMap::new();
+        bindings.insert("input1".to_string(), vec![7u8; 3]);
+        let program = assemble_with(
+            "<input1> input signtx  # unlock\n0x0a0b base64:AQID",
+            &bindings,
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![
+                0x00, 3, 0, 0, 0, 7, 7, 7,    // <input1>
+                0x1c, // input
+                0x21, // signtx
+                0x00, 2, 0, 0, 0, 0x0a, 0x0b, // 0x0a0b
+                0x00, 3, 0, 0, 0, 1, 2, 3, // base64:AQID
+            ]
+        );
+        assert_eq!(assemble("signtx raw:0005").unwrap(), vec![0x21, 0x00, 0x05]);
+    }
+
+    #[test]
+    fn push_notation() {
+        let program = assemble("push:2:0a0b push:0x1:0xff push:0:").unwrap();
+        assert_eq!(program, assemble("0x0a0b 0xff 0x").unwrap());
+        assert_eq!(
+            parse_all(&program),
+            vec![
+                Instruction::Push(2),
+                Instruction::Push(1),
+                Instruction::Push(0)
+            ]
+        );
+    }
+
+    #[test]
+    fn error_location() {
+        let err = assemble("nonce signtx\n  cloak:3 drop").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+
+        let err = assemble("drop frobnicate").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 6));
+
+        let err = assemble("# comment\n<missing>").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 1));
+
+        assert!(assemble("0xabc").is_err());
+        assert!(assemble("ext:1").is_err());
+        assert!(assemble("range:256").is_err());
+        assert!(assemble("push:2:0x00").is_err());
+        assert!(assemble("raw:0").is_err());
+        assert!(assemble("push:1").is_err());
+        assert!(assemble("push:1:zz").is_err());
+    }
+}
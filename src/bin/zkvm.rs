@@ -0,0 +1,272 @@
+extern crate curve25519_dalek;
+extern crate hex;
+extern crate zkvm;
+
+use curve25519_dalek::ristretto::CompressedRistretto;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process;
+
+use zkvm::{Entry, Predicate, Tx, TxID, UTXO, VM};
+
+const USAGE: &str = "Usage: zkvm [--hex] <command>
+
+Commands:
+    zkvm verify <tx-file>                 Verifies the transaction, prints its txid, cost and log
+    zkvm txid <log-file>                  Computes the txid from the log printed by `verify`
+    zkvm utxo <txid> <output-hex>         Computes the UTXO id of an output
+    zkvm asm <source-file> [name=hex...]  Assembles the program, resolving <name> with given data
+    zkvm disasm <program-file>            Prints the program as annotated assembly
+    zkvm predicate or <left> <right>      Computes the disjunction of two predicates
+    zkvm predicate program <program-file> Computes the predicate for a program
+
+Binary inputs are read from files with raw contents,
+or with hex-encoded contents if `--hex` is given.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let hex_input = args.iter().any(|a| a == "--hex");
+    let args: Vec<&str> = args
+        .iter()
+        .map(|a| a.as_str())
+        .filter(|a| *a != "--hex")
+        .collect();
+
+    let result = match &args[..] {
+        ["verify", path] => verify(path, hex_input),
+        ["txid", path] => txid(path),
+        ["utxo", txid, output] => utxo(txid, output),
+        ["disasm", path] => disasm(path, hex_input),
+        ["predicate", "or", left, right] => predicate_or(left, right),
+        ["predicate", "program", path] => predicate_program(path, hex_input),
+        _ if args.len() >= 2 && args[0] == "asm" => asm(args[1], &args[2..]),
+        _ => Err(USAGE.to_string()),
+    };
+
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+/// Verifies the transaction and prints its txid, cost and log.
+/// The output is a valid log file for the `txid` command.
+fn verify(path: &str, hex_input: bool) -> Result<(), String> {
+    let tx =
+        Tx::from_bytes(&read_bytes(path, hex_input)?).map_err(|e| format!("Invalid tx: {}", e))?;
+    let verified = VM::verify_tx(&tx).map_err(|e| format!("Invalid tx: {}", e))?;
+    println!("# txid {}", hex::encode(&verified.id.0));
+    println!(
+        "# cost multipliers={} range_proofs={} point_ops={} msm_size={}",
+        verified.cost.multipliers,
+        verified.cost.range_proofs,
+        verified.cost.point_ops,
+        verified.cost.msm_size
+    );
+    for entry in verified.log.iter() {
+        println!("{}", format_entry(entry));
+    }
+    Ok(())
+}
+
+/// Computes the txid from a log file.
+fn txid(path: &str) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    let mut log = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        log.push(parse_entry(line).map_err(|e| format!("{}:{}: {}", path, i + 1, e))?);
+    }
+    println!("{}", hex::encode(&TxID::from_log(&log).0));
+    Ok(())
+}
+
+/// Computes the UTXO id of an output.
+fn utxo(txid: &str, output: &str) -> Result<(), String> {
+    let txid = TxID(parse_hex32(txid)?);
+    let output = parse_hex(output)?;
+    println!("{}", hex::encode(&UTXO::from_output(&output, &txid).0));
+    Ok(())
+}
+
+/// Assembles the program and prints it hex-encoded.
+fn asm(path: &str, bindings: &[&str]) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    let mut map = HashMap::new();
+    for binding in bindings.iter() {
+        let mut parts = binding.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(data)) => map.insert(name.to_string(), parse_hex(data)?),
+            _ => return Err(format!("Invalid binding `{}`, expected name=hex", binding)),
+        };
+    }
+    let program = zkvm::assemble_with(&source, &map).map_err(|e| format!("{}:{}", path, e))?;
+    println!("{}", hex::encode(&program));
+    Ok(())
+}
+
+/// Prints the program as annotated assembly.
+fn disasm(path: &str, hex_input: bool) -> Result<(), String> {
+    let program = read_bytes(path, hex_input)?;
+    print!("{}", zkvm::disassemble(&program));
+    Ok(())
+}
+
+fn predicate_or(left: &str, right: &str) -> Result<(), String> {
+    let left = Predicate(CompressedRistretto(parse_hex32(left)?));
+    let right = Predicate(CompressedRistretto(parse_hex32(right)?));
+    let predicate = left.or(&right).map_err(|e| e.to_string())?;
+    println!("{}", hex::encode(predicate.0.as_bytes()));
+    Ok(())
+}
+
+fn predicate_program(path: &str, hex_input: bool) -> Result<(), String> {
+    let predicate = Predicate::program_predicate(&read_bytes(path, hex_input)?);
+    println!("{}", hex::encode(predicate.0.as_bytes()));
+    Ok(())
+}
+
+/// Formats the log entry as a line: entry type followed by its fields.
+fn format_entry(entry: &Entry) -> String {
+    match entry {
+        Entry::Header(version, mintime, maxtime) => {
+            format!("header {} {} {}", version, mintime, maxtime)
+        }
+        Entry::Issue(q, f) => format!(
+            "issue {} {}",
+            hex::encode(q.as_bytes()),
+            hex::encode(f.as_bytes())
+        ),
+        Entry::Retire(q, f) => format!(
+            "retire {} {}",
+            hex::encode(q.as_bytes()),
+            hex::encode(f.as_bytes())
+        ),
+        Entry::Input(utxo) => format!("input {}", hex::encode(&utxo.0)),
+        Entry::Nonce(predicate, maxtime) => {
+            format!("nonce {} {}", hex::encode(predicate.0.as_bytes()), maxtime)
+        }
+        Entry::Output(output) => format!("output {}", hex::encode(output)),
+        Entry::Data(data) => format!("data {}", hex::encode(data)),
+        Entry::Import => "import".to_string(),
+        Entry::Export => "export".to_string(),
+    }
+}
+
+/// Parses a line printed by `format_entry`.
+fn parse_entry(line: &str) -> Result<Entry, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let point = |s: &str| parse_hex32(s).map(CompressedRistretto);
+    let int = |s: &str| {
+        s.parse::<u64>()
+            .map_err(|_| format!("Invalid integer `{}`", s))
+    };
+    match &fields[..] {
+        ["header", version, mintime, maxtime] => {
+            Ok(Entry::Header(int(version)?, int(mintime)?, int(maxtime)?))
+        }
+        ["issue", q, f] => Ok(Entry::Issue(point(q)?, point(f)?)),
+        ["retire", q, f] => Ok(Entry::Retire(point(q)?, point(f)?)),
+        ["input", utxo] => Ok(Entry::Input(UTXO(parse_hex32(utxo)?))),
+        ["nonce", predicate, maxtime] => {
+            Ok(Entry::Nonce(Predicate(point(predicate)?), int(maxtime)?))
+        }
+        ["output", output] => Ok(Entry::Output(parse_hex(output)?)),
+        ["data", data] => Ok(Entry::Data(parse_hex(data)?)),
+        ["import"] => Ok(Entry::Import),
+        ["export"] => Ok(Entry::Export),
+        _ => Err(format!("Invalid log entry `{}`", line)),
+    }
+}
+
+/// Reads a file with raw binary contents, or with hex-encoded contents if `hex_input` is set.
+fn read_bytes(path: &str, hex_input: bool) -> Result<Vec<u8>, String> {
+    if hex_input {
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        hex::decode(text.trim()).map_err(|_| format!("Invalid hex contents in {}", path))
+    } else {
+        fs::read(path).map_err(|e| format!("Cannot read {}: {}", path, e))
+    }
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    hex::decode(s).map_err(|_| format!("Invalid hex string `{}`", s))
+}
+
+fn parse_hex32(s: &str) -> Result<[u8; 32], String> {
+    let bytes = parse_hex(s)?;
+    if bytes.len() != 32 {
+        return Err(format!("Expected 32 bytes, got {} in `{}`", bytes.len(), s));
+    }
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(&bytes);
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bulletproofs::r1cs::Prover;
+    use bulletproofs::{BulletproofGens, PedersenGens};
+    use merlin::Transcript;
+
+    /// Writes the contents to a fresh file in the temporary directory and returns its path.
+    fn temp_file(name: &str, contents: &[u8]) -> String {
+        let path = env::temp_dir().join(format!("zkvm-cli-{}-{}", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// Encodes an unsigned transaction with the program and a well-formed empty proof.
+    fn tx_bytes(program: &[u8]) -> Vec<u8> {
+        let bp_gens = BulletproofGens::new(64, 1);
+        let pc_gens = PedersenGens::default();
+        let mut transcript = Transcript::new(b"ZkVM.r1cs");
+        let proof = Prover::new(&bp_gens, &pc_gens, &mut transcript)
+            .prove()
+            .unwrap();
+
+        let mut bytes = Vec::new();
+        for x in [1u64, 0, 100].iter() {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(program.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(program);
+        bytes.extend_from_slice(&[0u8; 64]);
+        bytes.extend_from_slice(&proof.to_bytes());
+        bytes
+    }
+
+    #[test]
+    fn invalid_transactions() {
+        // `call` is not implemented by the VM.
+        let tx = tx_bytes(&[0x22]);
+        let error = verify(&temp_file("call.tx", &tx), false).unwrap_err();
+        assert!(error.starts_with("Invalid tx"));
+        assert!(error.contains("not supported"));
+
+        let hex_path = temp_file("call.hex", hex::encode(&tx).as_bytes());
+        assert!(verify(&hex_path, true)
+            .unwrap_err()
+            .contains("not supported"));
+        // Hex contents are not decoded unless asked to.
+        assert!(verify(&hex_path, false).is_err());
+
+        let truncated = temp_file("truncated.tx", &tx[..30]);
+        assert!(verify(&truncated, false)
+            .unwrap_err()
+            .starts_with("Invalid tx"));
+
+        let garbage = temp_file("garbage.hex", b"not hex");
+        assert!(verify(&garbage, true)
+            .unwrap_err()
+            .starts_with("Invalid hex"));
+        assert!(verify("/nonexistent/zkvm.tx", false)
+            .unwrap_err()
+            .starts_with("Cannot read"));
+    }
+}
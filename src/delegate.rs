@@ -0,0 +1,83 @@
+//! Programs signed for the `delegate` instruction.
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+
+use crate::errors::VMError;
+use crate::ops::Instruction;
+use crate::predicate::Predicate;
+use crate::signature::{Signature, VerificationKey};
+
+/// Program signed with the key of a contract's predicate, which `delegate` executes in place of the predicate.
+#[derive(Clone, Debug)]
+pub struct DelegatedProgram {
+    /// Program to be executed.
+    pub program: Vec<u8>,
+
+    /// Signature over the program.
+    pub signature: Signature,
+}
+
+impl DelegatedProgram {
+    /// Signs the program with the private key of the predicate.
+    pub fn sign(privkey: Scalar, program: Vec<u8>) -> Self {
+        let signature =
+            Signature::sign_single(&mut DelegatedProgram::transcript(&program), privkey);
+        DelegatedProgram { program, signature }
+    }
+
+    /// Verifies the signature over the program for the given predicate.
+    pub fn verify(&self, predicate: &Predicate) -> Result<(), VMError> {
+        self.signature.verify(
+            &mut DelegatedProgram::transcript(&self.program),
+            &[VerificationKey(predicate.0)],
+        )
+    }
+
+    /// Encodes the arguments of `delegate` as `push:prog push:sig`.
+    /// The contract must already be on the stack, and `delegate` itself is not written.
+    pub fn encode(&self, program: &mut Vec<u8>) {
+        Instruction::Push(self.program.len()).encode(program);
+        program.extend_from_slice(&self.program);
+        let sig = self.signature.to_bytes();
+        Instruction::Push(sig.len()).encode(program);
+        program.extend_from_slice(&sig);
+    }
+
+    /// Transcript used by `delegate` to verify the signature.
+    fn transcript(program: &[u8]) -> Transcript {
+        let mut t = Transcript::new(b"ZkVM.delegate");
+        t.commit_bytes(b"prog", program);
+        t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::Opcode;
+
+    #[test]
+    fn delegated_program() {
+        let privkey = Scalar::random(&mut rand::thread_rng());
+        let predicate = Predicate(VerificationKey::from_secret(&privkey).0);
+        let program = vec![Opcode::Drop.to_u8()];
+
+        let delegated = DelegatedProgram::sign(privkey, program.clone());
+        assert!(delegated.verify(&predicate).is_ok());
+
+        let other = Predicate(VerificationKey::from_secret(&Scalar::one()).0);
+        assert!(delegated.verify(&other).is_err());
+
+        let tampered = DelegatedProgram {
+            program: vec![Opcode::Dup.to_u8()],
+            signature: delegated.signature,
+        };
+        assert!(tampered.verify(&predicate).is_err());
+
+        let mut encoded = Vec::new();
+        delegated.encode(&mut encoded);
+        assert_eq!(encoded.len(), 5 + program.len() + 5 + 64);
+        assert_eq!(&encoded[5..6], &program[..]);
+        assert_eq!(&encoded[11..], &delegated.signature.to_bytes()[..]);
+    }
+}
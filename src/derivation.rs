@@ -0,0 +1,292 @@
+//! Hierarchical derivation of keys and predicates.
+//!
+//! An extended key is a key paired with a 32-byte derivation key.
+//! Children are derived by adding a scalar `f` computed from the extended public key and the child index:
+//! ```ascii
+//! T = Transcript("ZkVM.derivation")
+//! T.commit("xpub", P)
+//! T.commit("dk", dk)
+//! T.commit("index", index)
+//! child_prv = prv + f
+//! child_pub = P + f·B
+//! ```
+//! Since `f` depends only on public data, a watch-only wallet holding the extended public key
+//! derives the same verification keys as the wallet holding the extended private key.
+//!
+//! **Warning:** for the same reason, anyone who knows an extended public key and
+//! the private key of any child derived from it without hardening can compute
+//! the parent private key as `prv = child_prv - f`, and with it every other child.
+//! Use `Xprv::derive_hardened_key` for intermediate keys that are handed out
+//! to other parties or stored with less protection than the parent.
+//! A hardened child is derived from the parent private key:
+//! ```ascii
+//! T = Transcript("ZkVM.derivation.hardened")
+//! T.commit("xprv", prv)
+//! T.commit("dk", dk)
+//! T.commit("index", index)
+//! child_prv = T.challenge_scalar("prv")
+//! ```
+//! so its extended public key cannot be derived from the parent's one,
+//! and the child keys reveal nothing about the parent private key.
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use std::fmt;
+
+use crate::errors::VMError;
+use crate::gens;
+use crate::predicate::Predicate;
+use crate::signature::VerificationKey;
+use crate::transcript::TranscriptProtocol;
+
+/// Extended private key.
+#[derive(Clone)]
+pub struct Xprv {
+    scalar: Scalar,
+    xpub: Xpub,
+}
+
+/// Extended public key.
+#[derive(Copy, Clone, Debug)]
+pub struct Xpub {
+    point: RistrettoPoint,
+    precompressed: CompressedRistretto,
+    dk: [u8; 32],
+}
+
+impl Xprv {
+    /// Creates a root extended private key from a seed.
+    pub fn from_seed(seed: &[u8]) -> Self {
+        let mut t = Transcript::new(b"ZkVM.derivation.seed");
+        t.commit_bytes(b"seed", seed);
+        let scalar = t.challenge_scalar(b"scalar");
+        let mut dk = [0u8; 32];
+        t.challenge_bytes(b"dk", &mut dk);
+        Xprv::from_parts(scalar, dk)
+    }
+
+    fn from_parts(scalar: Scalar, dk: [u8; 32]) -> Self {
+        let point = scalar * gens::pedersen_gens().B;
+        Xprv {
+            scalar,
+            xpub: Xpub {
+                point,
+                precompressed: point.compress(),
+                dk,
+            },
+        }
+    }
+
+    /// Returns the extended public key.
+    pub fn to_xpub(&self) -> Xpub {
+        self.xpub
+    }
+
+    /// Derives the extended private key of the child at `index`,
+    /// matching `Xpub::derive_intermediate_key`.
+    /// The child private key together with this key's `Xpub` reveals this private key.
+    pub fn derive_intermediate_key(&self, index: u64) -> Xprv {
+        let (f, dk) = self.xpub.intermediate_factor(index);
+        Xprv::from_parts(self.scalar + f, dk)
+    }
+
+    /// Derives the hardened extended private key of the child at `index`.
+    /// It has no public counterpart and does not reveal this private key if leaked.
+    pub fn derive_hardened_key(&self, index: u64) -> Xprv {
+        let mut t = Transcript::new(b"ZkVM.derivation.hardened");
+        t.commit_scalar(b"xprv", &self.scalar);
+        t.commit_bytes(b"dk", &self.xpub.dk);
+        t.commit_u64(b"index", index);
+        let scalar = t.challenge_scalar(b"prv");
+        let mut dk = [0u8; 32];
+        t.challenge_bytes(b"dk", &mut dk);
+        Xprv::from_parts(scalar, dk)
+    }
+
+    /// Derives the private key of the leaf at `index`, matching `Xpub::derive_key`.
+    /// The leaf private key together with this key's `Xpub` reveals this private key.
+    pub fn derive_key(&self, index: u64) -> Scalar {
+        self.scalar + self.xpub.leaf_factor(index)
+    }
+
+    /// Decodes an extended private key from 64 bytes: the private key followed by the derivation key.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VMError> {
+        if bytes.len() != 64 {
+            return Err(VMError::FormatError);
+        }
+        let mut scalar = [0u8; 32];
+        let mut dk = [0u8; 32];
+        scalar.copy_from_slice(&bytes[..32]);
+        dk.copy_from_slice(&bytes[32..]);
+        let scalar = Scalar::from_canonical_bytes(scalar).ok_or(VMError::FormatError)?;
+        Ok(Xprv::from_parts(scalar, dk))
+    }
+
+    /// Encodes the extended private key as 64 bytes.
+    pub fn to_bytes(&self) -> [u8; 64] {
+        let mut buf = [0u8; 64];
+        buf[..32].copy_from_slice(self.scalar.as_bytes());
+        buf[32..].copy_from_slice(&self.xpub.dk);
+        buf
+    }
+}
+
+impl fmt::Debug for Xprv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Xprv")
+            .field("scalar", &"<redacted>")
+            .field("xpub", &self.xpub)
+            .finish()
+    }
+}
+
+impl Xpub {
+    /// Returns the verification key of this extended key.
+    pub fn verification_key(&self) -> VerificationKey {
+        VerificationKey(self.precompressed)
+    }
+
+    /// Derives the extended public key of the child at `index`.
+    pub fn derive_intermediate_key(&self, index: u64) -> Xpub {
+        let (f, dk) = self.intermediate_factor(index);
+        let point = self.point + f * gens::pedersen_gens().B;
+        Xpub {
+            point,
+            precompressed: point.compress(),
+            dk,
+        }
+    }
+
+    /// Derives the verification key of the leaf at `index`.
+    pub fn derive_key(&self, index: u64) -> VerificationKey {
+        let f = self.leaf_factor(index);
+        VerificationKey((self.point + f * gens::pedersen_gens().B).compress())
+    }
+
+    /// Derives the predicate locking an output to the leaf key at `index`.
+    pub fn derive_predicate(&self, index: u64) -> Predicate {
+        Predicate(self.derive_key(index).0)
+    }
+
+    /// Decodes an extended public key from 64 bytes: the verification key followed by the derivation key.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VMError> {
+        if bytes.len() != 64 {
+            return Err(VMError::FormatError);
+        }
+        let precompressed = CompressedRistretto::from_slice(&bytes[..32]);
+        let point = precompressed.decompress().ok_or(VMError::InvalidPoint)?;
+        let mut dk = [0u8; 32];
+        dk.copy_from_slice(&bytes[32..]);
+        Ok(Xpub {
+            point,
+            precompressed,
+            dk,
+        })
+    }
+
+    /// Encodes the extended public key as 64 bytes.
+    pub fn to_bytes(&self) -> [u8; 64] {
+        let mut buf = [0u8; 64];
+        buf[..32].copy_from_slice(self.precompressed.as_bytes());
+        buf[32..].copy_from_slice(&self.dk);
+        buf
+    }
+
+    fn prf(&self, index: u64) -> Transcript {
+        let mut t = Transcript::new(b"ZkVM.derivation");
+        t.commit_point(b"xpub", &self.precompressed);
+        t.commit_bytes(b"dk", &self.dk);
+        t.commit_u64(b"index", index);
+        t
+    }
+
+    fn intermediate_factor(&self, index: u64) -> (Scalar, [u8; 32]) {
+        let mut t = self.prf(index);
+        let f = t.challenge_scalar(b"f.intermediate");
+        let mut dk = [0u8; 32];
+        t.challenge_bytes(b"dk", &mut dk);
+        (f, dk)
+    }
+
+    fn leaf_factor(&self, index: u64) -> Scalar {
+        self.prf(index).challenge_scalar(b"f.leaf")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_derivation_matches_private() {
+        let xprv = Xprv::from_seed(b"seed").derive_intermediate_key(3);
+        let xpub = Xprv::from_seed(b"seed")
+            .to_xpub()
+            .derive_intermediate_key(3);
+        assert_eq!(xprv.to_xpub().to_bytes()[..], xpub.to_bytes()[..]);
+
+        for index in 0..3 {
+            let privkey = xprv.derive_key(index);
+            assert_eq!(
+                VerificationKey::from_secret(&privkey),
+                xpub.derive_key(index)
+            );
+            assert_eq!(xpub.derive_predicate(index).0, xpub.derive_key(index).0);
+        }
+        assert_ne!(xpub.derive_key(0), xpub.derive_key(1));
+        assert_ne!(
+            xpub.derive_key(0),
+            xpub.derive_intermediate_key(0).verification_key()
+        );
+    }
+
+    #[test]
+    fn extended_key_encoding() {
+        let xprv = Xprv::from_seed(b"seed");
+        let decoded = Xprv::from_bytes(&xprv.to_bytes()).unwrap();
+        assert_eq!(decoded.derive_key(5), xprv.derive_key(5));
+
+        let xpub = xprv.to_xpub();
+        let decoded = Xpub::from_bytes(&xpub.to_bytes()).unwrap();
+        assert_eq!(decoded.derive_key(5), xpub.derive_key(5));
+
+        assert_eq!(
+            Xpub::from_bytes(&[0xff; 64]).err(),
+            Some(VMError::InvalidPoint)
+        );
+        assert_eq!(
+            Xprv::from_bytes(&[0xff; 64]).err(),
+            Some(VMError::FormatError)
+        );
+        assert_eq!(Xpub::from_bytes(&[0; 32]).err(), Some(VMError::FormatError));
+    }
+
+    #[test]
+    fn hardened_derivation() {
+        let xprv = Xprv::from_seed(b"seed");
+        let xpub = xprv.to_xpub();
+
+        // A leaked non-hardened child key reveals the parent key.
+        let child = xprv.derive_intermediate_key(1);
+        let (f, _) = xpub.intermediate_factor(1);
+        assert_eq!(child.scalar - f, xprv.scalar);
+
+        let hardened = xprv.derive_hardened_key(1);
+        assert_eq!(
+            hardened.to_bytes()[..],
+            xprv.derive_hardened_key(1).to_bytes()[..]
+        );
+        assert_ne!(
+            hardened.to_xpub().to_bytes()[..],
+            xpub.derive_intermediate_key(1).to_bytes()[..]
+        );
+        assert_ne!(
+            hardened.to_bytes()[..],
+            xprv.derive_hardened_key(2).to_bytes()[..]
+        );
+
+        let debug = format!("{:?}", hardened);
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains(&format!("{:?}", hardened.scalar)));
+    }
+}
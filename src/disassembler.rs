@@ -0,0 +1,182 @@
+//! Disassembler that renders bytecode as annotated assembly.
+//!
+//! Each line contains an instruction in the assembler notation followed by a comment
+//! with its offset and annotations, so the output can be assembled back into the same program.
+//! Trailing bytes that do not form a complete instruction are written as a `raw:` token.
+//! Pushed strings are annotated as a point, a scalar or a nested program where they parse as one.
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+
+use crate::ops::Instruction;
+
+/// Width of the instruction column before the comment.
+const COLUMN_WIDTH: usize = 24;
+
+/// Maximum depth of nested programs that are disassembled;
+/// deeper strings are rendered as plain data.
+const MAX_NESTING: usize = 4;
+
+/// Renders the program as annotated assembly.
+pub fn disassemble(program: &[u8]) -> String {
+    let mut out = String::new();
+    write_program(program, 0, &mut out);
+    out
+}
+
+fn write_program(program: &[u8], depth: usize, out: &mut String) {
+    let mut offset = 0;
+    while offset < program.len() {
+        let (instr, size) = match parse_at(program, offset) {
+            Some(x) => x,
+            None => {
+                let rest = format!("raw:{}", hex::encode(&program[offset..]));
+                write_line(depth, &rest, offset, "truncated instruction", out);
+                return;
+            }
+        };
+        match instr {
+            Instruction::Push(len) => {
+                let data = &program[offset + size - len..offset + size];
+                let text = format!("0x{}", hex::encode(data));
+                if is_point(data) {
+                    write_line(depth, &text, offset, "point", out);
+                } else if is_scalar(data) {
+                    write_line(depth, &text, offset, "scalar", out);
+                } else if depth < MAX_NESTING && is_program(data) {
+                    write_line(depth, &text, offset, "program", out);
+                    write_program(data, depth + 1, out);
+                } else {
+                    write_line(depth, &text, offset, "", out);
+                }
+            }
+            Instruction::Ext(_) => {
+                write_line(depth, &instr.to_string(), offset, "extension opcode", out)
+            }
+            _ => write_line(depth, &instr.to_string(), offset, "", out),
+        }
+        offset += size;
+    }
+}
+
+/// Parses an instruction at a given offset, checking that the pushed string is complete.
+fn parse_at(program: &[u8], offset: usize) -> Option<(Instruction, usize)> {
+    let (instr, size) = Instruction::parse(&program[offset..])?;
+    if size > program.len() - offset {
+        return None;
+    }
+    Some((instr, size))
+}
+
+/// Writes a line; lines of nested programs are commented out entirely.
+fn write_line(depth: usize, text: &str, offset: usize, note: &str, out: &mut String) {
+    let location = if note.len() > 0 {
+        format!("{:04x}: {}", offset, note)
+    } else {
+        format!("{:04x}", offset)
+    };
+    if depth == 0 {
+        out.push_str(&format!("{:<w$} # {}\n", text, location, w = COLUMN_WIDTH));
+    } else {
+        let indent = "  ".repeat(depth);
+        out.push_str(&format!(
+            "{:<w$} # {}{:<w$} # {}\n",
+            "",
+            indent,
+            text,
+            location,
+            w = COLUMN_WIDTH
+        ));
+    }
+}
+
+fn is_point(data: &[u8]) -> bool {
+    data.len() == 32 && {
+        let mut buf = [0u8; 32];
+        buf.copy_from_slice(data);
+        CompressedRistretto(buf).decompress().is_some()
+    }
+}
+
+fn is_scalar(data: &[u8]) -> bool {
+    data.len() == 32 && {
+        let mut buf = [0u8; 32];
+        buf.copy_from_slice(data);
+        Scalar::from_canonical_bytes(buf).is_some()
+    }
+}
+
+/// Returns `true` if the string is a non-empty sequence of complete non-extension instructions.
+fn is_program(data: &[u8]) -> bool {
+    let mut offset = 0;
+    while offset < data.len() {
+        match parse_at(data, offset) {
+            Some((Instruction::Ext(_), _)) | None => return false,
+            Some((_, size)) => offset += size,
+        }
+    }
+    data.len() > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::ops::Opcode;
+    use bulletproofs::PedersenGens;
+
+    #[test]
+    fn round_trip() {
+        let source = "0x1f21 0x0a0b0c dup:1 cloak:3:2 range:64 output:1 ext:200 signtx";
+        let program = assemble(source).unwrap();
+        let text = disassemble(&program);
+        assert_eq!(assemble(&text).unwrap(), program);
+    }
+
+    #[test]
+    fn annotations() {
+        let point = PedersenGens::default().B.compress();
+        let program = assemble(&format!(
+            "0x{} 0x1f21 ext:200",
+            hex::encode(point.as_bytes())
+        ))
+        .unwrap();
+        let lines = disassemble(&program)
+            .lines()
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].ends_with("# 0000: point"));
+        assert!(lines[1].ends_with("# 0025: program"));
+        assert!(lines[2].trim_start().starts_with("#   nonce"));
+        assert!(lines[3].trim_start().starts_with("#   signtx"));
+        assert!(lines[4].starts_with("ext:200"));
+        assert!(lines[4].ends_with("# 002c: extension opcode"));
+    }
+
+    #[test]
+    fn deeply_nested_program() {
+        let mut program = vec![Opcode::Drop.to_u8()];
+        for _ in 0..1000 {
+            let mut outer = Vec::new();
+            Instruction::Push(program.len()).encode(&mut outer);
+            outer.extend_from_slice(&program);
+            program = outer;
+        }
+        let text = disassemble(&program);
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), MAX_NESTING + 1);
+        assert!(lines[MAX_NESTING - 1].ends_with(": program"));
+        assert!(!lines[MAX_NESTING].ends_with(": program"));
+        assert_eq!(assemble(&text).unwrap(), program);
+    }
+
+    #[test]
+    fn truncated_program() {
+        let program = [0x21, 0x00, 0x05, 0x00, 0x00, 0x00, 0x01];
+        let text = disassemble(&program);
+        let last = text.lines().last().unwrap();
+        assert!(last.starts_with("raw:000500000001 "));
+        assert!(last.ends_with("# 0001: truncated instruction"));
+        assert_eq!(assemble(&text).unwrap(), program);
+    }
+}
@@ -24,6 +24,15 @@ pub fn read_u32<'a>(slice: &'a [u8]) -> Result<(u32, &'a [u8]), VMError> {
     Ok((x, &slice[4..]))
 }
 
+/// Reads the LE64-encoded integer and returns the subsequent slice
+pub fn read_u64<'a>(slice: &'a [u8]) -> Result<(u64, &'a [u8]), VMError> {
+    if slice.len() < 8 {
+        return Err(VMError::FormatError);
+    }
+    let x = LittleEndian::read_u64(slice);
+    Ok((x, &slice[8..]))
+}
+
 /// Reads the LE32-encoded integer as `usize` and returns the subsequent slice
 pub fn read_usize<'a>(slice: &'a [u8]) -> Result<(usize, &'a [u8]), VMError> {
     let (n, rest) = read_u32(slice)?;
@@ -80,6 +89,13 @@ pub fn write_u32<'a>(x: u32, target: &mut Vec<u8>) {
     target.extend_from_slice(&buf);
 }
 
+// Writes a LE64-encoded integer
+pub fn write_u64<'a>(x: u64, target: &mut Vec<u8>) {
+    let mut buf = [0u8; 8];
+    LittleEndian::write_u64(&mut buf, x);
+    target.extend_from_slice(&buf);
+}
+
 /// Reads a 32-byte array and returns the subsequent slice
 pub fn write_bytes(x: &[u8], target: &mut Vec<u8>) {
     target.extend_from_slice(&x);
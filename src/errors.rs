@@ -1,4 +1,8 @@
 //! Errors related to proving and verifying proofs.
+use core::fmt;
+
+use crate::ops::Instruction;
+use crate::point_ops::PointOpOrigin;
 
 /// Represents an error in proof creation, verification, or parsing.
 #[derive(Fail, Clone, Debug, Eq, PartialEq)]
@@ -19,6 +23,10 @@ pub enum VMError {
     #[fail(display = "Transaction version does not permit extension instructions.")]
     ExtensionsNotAllowed,
 
+    /// This error occurs when the program uses an instruction that the VM does not implement yet.
+    #[fail(display = "Instruction is not supported.")]
+    UnsupportedInstruction,
+
     /// This error occurs when an instruction requires a copyable type, but a linear type is encountered.
     #[fail(display = "Item is not a copyable type.")]
     TypeNotCopyable,
@@ -78,4 +86,232 @@ pub enum VMError {
     /// This error occurs when R1CS gadget reports and error due to inconsistent input
     #[fail(display = "R1CS detected inconsistent input")]
     R1CSInconsistency,
+
+    /// This error occurs when an inclusion proof does not match the UTXO accumulator
+    #[fail(display = "Merkle proof is invalid")]
+    InvalidMerkleProof,
+
+    /// This error occurs when the program is larger than allowed by the VM limits.
+    #[fail(display = "Program size exceeds the limit")]
+    ProgramTooLarge,
+
+    /// This error occurs when the stack has more items than allowed by the VM limits.
+    #[fail(display = "Stack size exceeds the limit")]
+    StackLimitExceeded,
+
+    /// This error occurs when programs are nested deeper than allowed by the VM limits.
+    #[fail(display = "Program nesting depth exceeds the limit")]
+    RunDepthLimitExceeded,
+
+    /// This error occurs when the constraint system has more multipliers than allowed by the VM limits.
+    #[fail(display = "Number of constraint system multipliers exceeds the limit")]
+    MultiplierLimitExceeded,
+
+    /// This error occurs when the deferred point operations have more points than allowed by the VM limits.
+    #[fail(display = "Number of deferred points exceeds the limit")]
+    DeferredPointLimitExceeded,
+}
+
+/// Represents a failure of the VM together with the location where it occurred.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExecutionError {
+    /// Reason of the failure.
+    pub error: VMError,
+
+    /// Instruction that failed, if the failure occurred while executing one.
+    pub instruction: Option<Instruction>,
+
+    /// Byte offset of the failing instruction within the current program,
+    /// if the failure occurred while executing the program.
+    pub offset: Option<usize>,
+
+    /// Number of suspended programs below the current one,
+    /// if the failure occurred while executing the program.
+    pub depth: Option<usize>,
+
+    /// Index of the failing deferred point operation
+    /// if the error is `VMError::PointOperationFailed`.
+    pub point_op: Option<usize>,
+
+    /// Statement checked by the failing deferred point operation.
+    pub point_op_origin: Option<PointOpOrigin>,
+}
+
+impl From<VMError> for ExecutionError {
+    fn from(error: VMError) -> Self {
+        ExecutionError {
+            error,
+            instruction: None,
+            offset: None,
+            depth: None,
+            point_op: None,
+            point_op_origin: None,
+        }
+    }
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut context = Vec::new();
+        if let Some(instruction) = self.instruction {
+            context.push(format!("instruction `{}`", instruction));
+        }
+        if let Some(offset) = self.offset {
+            context.push(format!("offset {}", offset));
+        }
+        if let Some(depth) = self.depth {
+            context.push(format!("depth {}", depth));
+        }
+        if let Some(index) = self.point_op {
+            context.push(format!("point operation #{}", index));
+        }
+        if let Some(origin) = self.point_op_origin {
+            context.push(origin.to_string());
+        }
+        if context.is_empty() {
+            write!(f, "{}", self.error)
+        } else {
+            write!(f, "{} ({})", self.error, context.join(", "))
+        }
+    }
+}
+
+impl failure::Fail for ExecutionError {
+    fn cause(&self) -> Option<&failure::Fail> {
+        Some(&self.error)
+    }
+}
+
+/// Represents an error in a batch of transactions, pointing to the offending transaction.
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+#[fail(display = "Transaction {} is invalid: {}", index, error)]
+pub struct BatchError {
+    /// Index of the invalid transaction in the batch.
+    pub index: usize,
+
+    /// Reason why the transaction is invalid.
+    pub error: ExecutionError,
+}
+
+/// Represents an error in admitting a transaction to the mempool.
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+pub enum MempoolError {
+    /// This error occurs when the transaction fails verification.
+    #[fail(display = "Transaction is invalid: {}", _0)]
+    InvalidTx(ExecutionError),
+
+    /// This error occurs when the transaction is already in the mempool.
+    #[fail(display = "Transaction is already in the mempool")]
+    DuplicateTx,
+
+    /// This error occurs when the transaction spends an input already spent by another transaction.
+    #[fail(display = "Transaction spends an input already spent in the mempool")]
+    InputConflict,
+
+    /// This error occurs when the transaction uses a nonce already used by another transaction.
+    #[fail(display = "Transaction uses a nonce already used in the mempool")]
+    NonceConflict,
+
+    /// This error occurs when the transaction maxtime has passed.
+    #[fail(display = "Transaction has expired")]
+    Expired,
+}
+
+/// Represents an error in the interactive multi-party signing protocol.
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+pub enum MuSigError {
+    /// This error occurs when the signer's key is not among the aggregated keys.
+    #[fail(display = "Signer's key is not among the aggregated keys")]
+    UnknownSigner,
+
+    /// This error occurs when one of the aggregated keys is not a valid point.
+    #[fail(display = "Verification key {} is not a valid point", _0)]
+    InvalidKey(usize),
+
+    /// This error occurs when the number of received messages does not match the number of parties.
+    #[fail(display = "Expected {} messages, got {}", expected, actual)]
+    WrongNumberOfMessages {
+        /// Number of parties.
+        expected: usize,
+        /// Number of received messages.
+        actual: usize,
+    },
+
+    /// This error occurs when a party's nonce commitment does not match its precommitment.
+    #[fail(
+        display = "Nonce commitment of party {} does not match its precommitment",
+        _0
+    )]
+    NonceMismatch(usize),
+
+    /// This error occurs when the message at the party's own position is not the one it sent.
+    #[fail(display = "Received message at the party's own position was not sent by it")]
+    OwnMessageMismatch,
+
+    /// This error occurs when a party's signature share is invalid.
+    #[fail(display = "Signature share of party {} is invalid", _0)]
+    InvalidShare(usize),
+
+    /// This error occurs when a message cannot be decoded.
+    #[fail(display = "Message is malformed")]
+    FormatError,
+}
+
+/// Represents an error in generating or using a threshold key.
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+pub enum ThresholdError {
+    /// This error occurs when the threshold is zero or exceeds the number of participants,
+    /// or when the participant's index is out of range.
+    #[fail(display = "Invalid threshold parameters")]
+    InvalidParameters,
+
+    /// This error occurs when the number of received messages does not match the number of participants.
+    #[fail(display = "Expected {} messages, got {}", expected, actual)]
+    WrongNumberOfMessages {
+        /// Number of participants.
+        expected: usize,
+        /// Number of received messages.
+        actual: usize,
+    },
+
+    /// This error occurs when a participant's commitment has a wrong number of points or an invalid point.
+    #[fail(display = "Commitment of participant {} is invalid", _0)]
+    InvalidCommitment(usize),
+
+    /// This error occurs when a participant's commitment lacks a valid proof of knowledge of its constant term.
+    #[fail(
+        display = "Commitment of participant {} has an invalid proof of knowledge",
+        _0
+    )]
+    InvalidProof(usize),
+
+    /// This error occurs when a share dealt by a participant does not match its commitment.
+    #[fail(
+        display = "Share dealt by participant {} does not match its commitment",
+        _0
+    )]
+    InvalidShare(usize),
+
+    /// This error occurs when the signers are fewer than the threshold, repeated, out of range,
+    /// or do not include the signing participant.
+    #[fail(display = "Invalid set of signers")]
+    InvalidSignerSet,
+
+    /// This error occurs when a message cannot be decoded.
+    #[fail(display = "Message is malformed")]
+    FormatError,
+}
+
+/// Represents an error in assembling a program from its textual notation.
+#[derive(Fail, Clone, Debug, Eq, PartialEq)]
+#[fail(display = "{}:{}: {}", line, column, message)]
+pub struct AsmError {
+    /// Line number of the offending item (starting with 1).
+    pub line: usize,
+
+    /// Column of the offending item (starting with 1).
+    pub column: usize,
+
+    /// Description of the error.
+    pub message: String,
 }
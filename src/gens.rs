@@ -0,0 +1,61 @@
+//! Process-wide cache of the generators for commitments and constraint system proofs.
+//!
+//! Generators are expensive to compute, so they are created once and shared.
+//! Bulletproofs generators grow on demand when a constraint system needs more of them.
+use bulletproofs::{BulletproofGens, PedersenGens};
+use std::sync::{Arc, RwLock};
+
+/// Capacity of the bulletproofs generators created on first use.
+const INITIAL_CAPACITY: usize = 64;
+
+lazy_static! {
+    static ref PEDERSEN_GENS: PedersenGens = PedersenGens::default();
+    static ref BULLETPROOF_GENS: RwLock<Arc<BulletproofGens>> =
+        RwLock::new(Arc::new(BulletproofGens::new(INITIAL_CAPACITY, 1)));
+}
+
+/// Returns the Pedersen commitment generators.
+pub fn pedersen_gens() -> &'static PedersenGens {
+    &PEDERSEN_GENS
+}
+
+/// Returns the bulletproofs generators with at least the given capacity,
+/// growing the cached generators to the next power of two if necessary.
+pub fn bulletproof_gens(capacity: usize) -> Arc<BulletproofGens> {
+    {
+        let gens = read_cache();
+        if gens.gens_capacity >= capacity {
+            return gens;
+        }
+    }
+    let mut cache = BULLETPROOF_GENS
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    // Another thread may have grown the generators while we were waiting for the lock.
+    if cache.gens_capacity < capacity {
+        *cache = Arc::new(BulletproofGens::new(capacity.next_power_of_two(), 1));
+    }
+    cache.clone()
+}
+
+fn read_cache() -> Arc<BulletproofGens> {
+    BULLETPROOF_GENS
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_on_demand() {
+        let gens = bulletproof_gens(0);
+        assert!(gens.gens_capacity >= INITIAL_CAPACITY);
+
+        let larger = bulletproof_gens(gens.gens_capacity + 1);
+        assert!(larger.gens_capacity > gens.gens_capacity);
+        assert!(bulletproof_gens(0).gens_capacity >= larger.gens_capacity);
+    }
+}
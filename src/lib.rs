@@ -1,27 +1,69 @@
+extern crate base64;
 extern crate byteorder;
 extern crate core;
+extern crate hex;
 extern crate rand;
 
 extern crate bulletproofs;
 extern crate curve25519_dalek;
 extern crate merlin;
+extern crate rayon;
 extern crate spacesuit;
 extern crate subtle;
 
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate lazy_static;
 
+mod adaptor;
+mod analyzer;
+mod assembler;
+mod delegate;
+mod derivation;
+mod disassembler;
 mod encoding;
 mod errors;
+mod gens;
+mod limits;
+mod mempool;
+mod musig;
 mod ops;
 mod point_ops;
 mod predicate;
+mod predicate_tree;
 mod signature;
+mod threshold;
+mod tracer;
 mod transcript;
 mod txlog;
 mod types;
+mod utreexo;
 mod vm;
 
-pub use self::errors::VMError;
+pub use self::adaptor::AdaptorSignature;
+pub use self::analyzer::{analyze, Analysis, AnalysisStep, ItemType, UnconsumedItem};
+pub use self::assembler::{assemble, assemble_with};
+pub use self::delegate::DelegatedProgram;
+pub use self::derivation::{Xprv, Xpub};
+pub use self::disassembler::disassemble;
+pub use self::errors::{
+    AsmError, BatchError, ExecutionError, MempoolError, MuSigError, ThresholdError, VMError,
+};
+pub use self::gens::{bulletproof_gens, pedersen_gens};
+pub use self::limits::{CostReport, VMLimits};
+pub use self::mempool::Mempool;
+pub use self::musig::{
+    NonceCommitment, NoncePrecommitment, Party, PartyAwaitingCommitments,
+    PartyAwaitingPrecommitments, PartyAwaitingShares, Share,
+};
+pub use self::ops::{Instruction, Opcode};
+pub use self::point_ops::{PointOp, PointOpOrigin};
+pub use self::predicate::Predicate;
+pub use self::predicate_tree::{Branch, PredicateLeaf, PredicateTree};
+pub use self::signature::{Signature, VerificationKey};
+pub use self::threshold::{KeygenCommitment, KeygenParticipant, SecretShare, ThresholdKey};
+pub use self::tracer::{ItemSummary, TraceStep, Tracer};
 pub use self::txlog::{Entry, TxID, UTXO};
-pub use self::vm::{Tx, VerifiedTx, VM};
+pub use self::utreexo::{Forest, ForestUpdate, InclusionProof};
+pub use self::vm::{DryRun, Tx, UnsignedTx, VerifiedTx, VM};
@@ -0,0 +1,130 @@
+//! Resource limits and execution cost accounting.
+use crate::point_ops::PointOp;
+
+/// Limits on resources used by the VM while verifying a transaction.
+/// Exceeding any of them fails the verification with a corresponding `VMError`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VMLimits {
+    /// Maximum size of the transaction program in bytes.
+    pub max_program_size: usize,
+
+    /// Maximum number of items on the VM stack.
+    pub max_stack_items: usize,
+
+    /// Maximum number of suspended programs below the current one.
+    pub max_run_depth: usize,
+
+    /// Maximum number of multipliers in the constraint system.
+    pub max_multipliers: usize,
+
+    /// Maximum number of points in the deferred point operations.
+    pub max_deferred_points: usize,
+}
+
+impl Default for VMLimits {
+    fn default() -> Self {
+        VMLimits {
+            max_program_size: 1 << 20,
+            max_stack_items: 1 << 16,
+            max_run_depth: 64,
+            max_multipliers: 1 << 16,
+            max_deferred_points: 1 << 16,
+        }
+    }
+}
+
+/// Resources used by a transaction, for estimating its fee.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CostReport {
+    /// Number of multipliers in the constraint system.
+    pub multipliers: usize,
+
+    /// Number of range proofs, including the ones for the `cloak` outputs.
+    pub range_proofs: usize,
+
+    /// Number of deferred point operations, including the transaction signature.
+    pub point_ops: usize,
+
+    /// Number of points in the multi-scalar multiplication verifying the deferred point operations.
+    pub msm_size: usize,
+}
+
+impl CostReport {
+    /// Counts the range proof multipliers: one per bit.
+    pub(crate) fn add_range_proof(&mut self, bitrange: usize) {
+        self.multipliers += bitrange;
+        self.range_proofs += 1;
+    }
+
+    /// Counts the multipliers of a `cloak` with `m` inputs and `n` outputs,
+    /// following the structure of the spacesuit gadget: one multiplier per allocated value,
+    /// `2·(k-1)` per shuffle of `k` values, one per mix of two values,
+    /// and a 64-bit range proof per output.
+    pub(crate) fn add_cloak(&mut self, m: usize, n: usize) {
+        let k = m.max(n);
+        let pairs = |k: usize| k.saturating_sub(1);
+        let inner = |k: usize| k.saturating_sub(2);
+        // Shuffled inputs, intermediate and merged values, split values,
+        // intermediate and unshuffled outputs, and padding of the middle shuffle.
+        let values = m + inner(m) + m + n + inner(n) + n + (k - m.min(n));
+        let shuffles = 2 * (pairs(m) + pairs(k) + pairs(n));
+        let mixes = pairs(m) + pairs(n);
+        self.multipliers += values + shuffles + mixes;
+        for _ in 0..n {
+            self.add_range_proof(64);
+        }
+    }
+
+    /// Counts a deferred point operation.
+    pub(crate) fn add_point_op(&mut self, op: &PointOp) {
+        self.add_points(op.arbitrary.len());
+    }
+
+    /// Counts the point operation verifying an aggregated signature by `keys` keys,
+    /// which weighs each key and the nonce commitment.
+    pub(crate) fn add_signature(&mut self, keys: usize) {
+        self.add_points(keys + 1);
+    }
+
+    fn add_points(&mut self, points: usize) {
+        if self.point_ops == 0 {
+            // The batch always includes the primary and secondary generators.
+            self.msm_size = 2;
+        }
+        self.point_ops += 1;
+        self.msm_size += points;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::ristretto::CompressedRistretto;
+    use curve25519_dalek::scalar::Scalar;
+
+    #[test]
+    fn cost_accounting() {
+        let mut cost = CostReport::default();
+        cost.add_range_proof(64);
+        cost.add_cloak(1, 1);
+        assert_eq!(cost.multipliers, 64 + 4 + 64);
+        assert_eq!(cost.range_proofs, 2);
+
+        cost.add_cloak(2, 3);
+        assert_eq!(
+            cost.multipliers,
+            132 + 12 + 2 * (1 + 2 + 2) + (1 + 2) + 3 * 64
+        );
+        assert_eq!(cost.range_proofs, 5);
+
+        let op = PointOp {
+            primary: Some(Scalar::one()),
+            secondary: None,
+            arbitrary: vec![(Scalar::one(), CompressedRistretto([0u8; 32])); 3],
+        };
+        cost.add_point_op(&op);
+        cost.add_point_op(&op);
+        assert_eq!(cost.point_ops, 2);
+        assert_eq!(cost.msm_size, 2 + 6);
+    }
+}
@@ -0,0 +1,433 @@
+//! In-process pool of verified transactions awaiting inclusion in a block.
+//!
+//! Transactions are verified on admission and indexed by the UTXOs they spend
+//! and by the nonces they use, so conflicting transactions are rejected upfront.
+use merlin::Transcript;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::errors::MempoolError;
+use crate::txlog::{Entry, TxID, UTXO};
+use crate::vm::{Tx, VerifiedTx, VM};
+
+/// Pool of verified non-conflicting transactions.
+#[derive(Default)]
+pub struct Mempool {
+    /// Transactions by their IDs.
+    entries: HashMap<TxID, MempoolEntry>,
+
+    /// Sequence number of the next admitted transaction.
+    next_seq: u64,
+
+    /// Hashes of the encoded transactions in the pool.
+    hashes: HashSet<[u8; 32]>,
+
+    /// Transactions spending a given UTXO.
+    spent: HashMap<UTXO, TxID>,
+
+    /// Transactions using a given nonce (predicate and maxtime).
+    nonces: HashMap<([u8; 32], u64), TxID>,
+
+    /// Transactions creating a given UTXO.
+    outputs: HashMap<UTXO, TxID>,
+
+    /// Transactions by their maxtime.
+    expiry: BTreeMap<u64, Vec<TxID>>,
+}
+
+struct MempoolEntry {
+    tx: Tx,
+    verified: VerifiedTx,
+    /// Hash of the encoded transaction.
+    hash: [u8; 32],
+    /// Order of admission.
+    seq: u64,
+}
+
+impl Mempool {
+    /// Creates an empty mempool.
+    pub fn new() -> Self {
+        Mempool::default()
+    }
+
+    /// Returns the number of transactions in the pool.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the pool has no transactions.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns `true` if the pool contains a transaction with a given ID.
+    pub fn contains(&self, txid: &TxID) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    /// Verifies the transaction and adds it to the pool.
+    /// Fails if the transaction is expired at time `now`, invalid,
+    /// or conflicts with one of the transactions in the pool.
+    /// Resubmitted transactions are detected by their encoding before they are verified.
+    pub fn add(&mut self, tx: Tx, now: u64) -> Result<TxID, MempoolError> {
+        if tx.maxtime < now {
+            return Err(MempoolError::Expired);
+        }
+        let hash = Self::tx_hash(&tx);
+        if self.hashes.contains(&hash) {
+            return Err(MempoolError::DuplicateTx);
+        }
+
+        let verified = VM::verify_tx(&tx).map_err(MempoolError::InvalidTx)?;
+        let txid = verified.id;
+        // Same transaction with a different signature or proof.
+        if self.contains(&txid) {
+            return Err(MempoolError::DuplicateTx);
+        }
+
+        // Check conflicts with the pool and within the transaction itself.
+        let mut inputs = HashSet::new();
+        let mut nonces = HashSet::new();
+        for entry in verified.log.iter() {
+            match entry {
+                Entry::Input(utxo) => {
+                    if self.spent.contains_key(utxo) || !inputs.insert(*utxo) {
+                        return Err(MempoolError::InputConflict);
+                    }
+                }
+                Entry::Nonce(predicate, maxtime) => {
+                    let nonce = (predicate.0.to_bytes(), *maxtime);
+                    if self.nonces.contains_key(&nonce) || !nonces.insert(nonce) {
+                        return Err(MempoolError::NonceConflict);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for utxo in inputs.into_iter() {
+            self.spent.insert(utxo, txid);
+        }
+        for nonce in nonces.into_iter() {
+            self.nonces.insert(nonce, txid);
+        }
+        for utxo in Self::created_utxos(&verified) {
+            self.outputs.insert(utxo, txid);
+        }
+        self.expiry
+            .entry(verified.maxtime)
+            .or_insert_with(Vec::new)
+            .push(txid);
+        self.hashes.insert(hash);
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.entries.insert(
+            txid,
+            MempoolEntry {
+                tx,
+                verified,
+                hash,
+                seq,
+            },
+        );
+        Ok(txid)
+    }
+
+    /// Removes a transaction from the pool (e.g. when it is included in a block).
+    pub fn remove(&mut self, txid: &TxID) -> Option<Tx> {
+        let entry = self.entries.remove(txid)?;
+        self.hashes.remove(&entry.hash);
+        for log_entry in entry.verified.log.iter() {
+            match log_entry {
+                Entry::Input(utxo) => {
+                    self.spent.remove(utxo);
+                }
+                Entry::Nonce(predicate, maxtime) => {
+                    self.nonces.remove(&(predicate.0.to_bytes(), *maxtime));
+                }
+                _ => {}
+            }
+        }
+        for utxo in Self::created_utxos(&entry.verified) {
+            self.outputs.remove(&utxo);
+        }
+        let maxtime = entry.verified.maxtime;
+        let now_empty = match self.expiry.get_mut(&maxtime) {
+            Some(ids) => {
+                ids.retain(|id| id != txid);
+                ids.is_empty()
+            }
+            None => false,
+        };
+        if now_empty {
+            self.expiry.remove(&maxtime);
+        }
+        Some(entry.tx)
+    }
+
+    /// Removes transactions whose maxtime is before `now`,
+    /// together with the transactions spending their outputs.
+    /// Returns the IDs of the removed transactions.
+    pub fn evict_expired(&mut self, now: u64) -> Vec<TxID> {
+        let mut evicted: Vec<TxID> = self
+            .expiry
+            .range(..now)
+            .flat_map(|(_, ids)| ids.iter().cloned())
+            .collect();
+        let mut seen: HashSet<TxID> = evicted.iter().cloned().collect();
+
+        let mut i = 0;
+        while i < evicted.len() {
+            let entry = &self.entries[&evicted[i]];
+            for utxo in Self::created_utxos(&entry.verified) {
+                if let Some(child) = self.spent.get(&utxo) {
+                    if seen.insert(*child) {
+                        evicted.push(*child);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        for txid in evicted.iter() {
+            self.remove(txid);
+        }
+        evicted
+    }
+
+    /// Returns transactions valid at time `now` ordered so that
+    /// every transaction comes after the transactions whose outputs it spends.
+    /// Transactions depending on ones that are not valid at `now` are left out.
+    pub fn candidate_block(&self, now: u64) -> Vec<&Tx> {
+        let mut pending: Vec<&MempoolEntry> = self
+            .entries
+            .values()
+            .filter(|e| e.verified.mintime <= now && now <= e.verified.maxtime)
+            .collect();
+        pending.sort_by_key(|e| e.seq);
+
+        let mut block = Vec::with_capacity(pending.len());
+        let mut placed = HashSet::new();
+        loop {
+            let remaining = pending.len();
+            pending.retain(|e| {
+                if self.parents(&e.verified).all(|p| placed.contains(&p)) {
+                    placed.insert(e.verified.id);
+                    block.push(&e.tx);
+                    false
+                } else {
+                    true
+                }
+            });
+            if pending.len() == remaining {
+                break;
+            }
+        }
+        block
+    }
+
+    /// Returns IDs of the pool transactions whose outputs are spent by `tx`.
+    fn parents<'a>(&'a self, tx: &'a VerifiedTx) -> impl Iterator<Item = TxID> + 'a {
+        tx.log.iter().filter_map(move |entry| match entry {
+            Entry::Input(utxo) => self.outputs.get(utxo).cloned(),
+            _ => None,
+        })
+    }
+
+    /// Returns UTXO identifiers of the outputs created by `tx`.
+    fn created_utxos<'a>(tx: &'a VerifiedTx) -> impl Iterator<Item = UTXO> + 'a {
+        tx.log.iter().filter_map(move |entry| match entry {
+            Entry::Output(output) => Some(UTXO::from_output(output, &tx.id)),
+            _ => None,
+        })
+    }
+
+    /// Hashes the encoded transaction, including its signature and proof.
+    fn tx_hash(tx: &Tx) -> [u8; 32] {
+        let mut t = Transcript::new(b"ZkVM.mempool");
+        t.commit_bytes(b"tx", &tx.to_bytes());
+        let mut hash = [0u8; 32];
+        t.challenge_bytes(b"hash", &mut hash);
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::VMError;
+    use crate::ops::Opcode;
+    use crate::vm::tests::{key, make_tx, program, spend};
+
+    fn nonce_tx(privkey: u64, maxtime: u64) -> Tx {
+        let prog = program("<k> nonce signtx", &[("k", key(privkey))]);
+        make_tx(prog, 0, maxtime, &[privkey])
+    }
+
+    /// Transaction using a nonce of key 1 and creating an output locked to key 2.
+    fn parent_tx(mintime: u64, maxtime: u64) -> Tx {
+        let prog = program(
+            "<k> nonce signtx 0x01 <k2> output:1",
+            &[("k", key(1)), ("k2", key(2))],
+        );
+        make_tx(prog, mintime, maxtime, &[1])
+    }
+
+    fn child_tx(parent: &Tx, maxtime: u64) -> Tx {
+        let prog = program("<input> input signtx drop", &[("input", spend(parent, 0))]);
+        make_tx(prog, 0, maxtime, &[2])
+    }
+
+    #[test]
+    fn nonce_conflicts() {
+        let mut pool = Mempool::new();
+        pool.add(nonce_tx(1, 100), 0).unwrap();
+
+        // Same nonce in a different transaction.
+        assert_eq!(
+            pool.add(parent_tx(0, 100), 0),
+            Err(MempoolError::NonceConflict)
+        );
+
+        // Same nonce twice within one transaction.
+        let prog = program("<k> nonce signtx <k> nonce signtx", &[("k", key(3))]);
+        let tx = make_tx(prog, 0, 100, &[3, 3]);
+        assert_eq!(pool.add(tx, 0), Err(MempoolError::NonceConflict));
+
+        // Same key with another maxtime is a different nonce.
+        assert!(pool.add(nonce_tx(1, 101), 0).is_ok());
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn input_conflicts() {
+        let parent = parent_tx(0, 100);
+        let input = spend(&parent, 0);
+        let mut pool = Mempool::new();
+        pool.add(child_tx(&parent, 100), 0).unwrap();
+
+        // Same input in a different transaction.
+        let prog = program(
+            "<input> input signtx <k> output:1",
+            &[("input", input.clone()), ("k", key(3))],
+        );
+        let tx = make_tx(prog, 0, 100, &[2]);
+        assert_eq!(pool.add(tx, 0), Err(MempoolError::InputConflict));
+
+        // Same input twice within one transaction.
+        let mut pool = Mempool::new();
+        let prog = program(
+            "<input> input signtx drop <input> input signtx drop",
+            &[("input", input)],
+        );
+        let tx = make_tx(prog, 0, 100, &[2, 2]);
+        assert_eq!(pool.add(tx, 0), Err(MempoolError::InputConflict));
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn duplicate_and_expired() {
+        let mut pool = Mempool::new();
+        let txid = pool.add(nonce_tx(1, 100), 0).unwrap();
+        assert!(pool.contains(&txid));
+
+        // Signatures differ, but the transaction ID is the same.
+        assert_eq!(
+            pool.add(nonce_tx(1, 100), 0),
+            Err(MempoolError::DuplicateTx)
+        );
+
+        // Byte-identical resubmission is rejected before verification.
+        let tx = nonce_tx(2, 100);
+        let copy = Tx::from_bytes(&tx.to_bytes()).unwrap();
+        pool.add(tx, 0).unwrap();
+        assert_eq!(pool.add(copy, 0), Err(MempoolError::DuplicateTx));
+        pool.remove(&txid);
+        assert!(pool.add(nonce_tx(1, 100), 0).is_ok());
+        assert_eq!(pool.add(nonce_tx(3, 100), 101), Err(MempoolError::Expired));
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn unsupported_instructions() {
+        let mut pool = Mempool::new();
+        for opcode in [
+            Opcode::Var,
+            Opcode::Mintime,
+            Opcode::Blind,
+            Opcode::Borrow,
+            Opcode::Import,
+            Opcode::Log,
+            Opcode::Call,
+            Opcode::Left,
+            Opcode::Delegate,
+        ]
+        .iter()
+        {
+            let mut tx = nonce_tx(1, 100);
+            tx.program = vec![opcode.to_u8()];
+            match pool.add(tx, 0) {
+                Err(MempoolError::InvalidTx(e)) => {
+                    assert_eq!(e.error, VMError::UnsupportedInstruction);
+                    assert_eq!(e.offset, Some(0));
+                }
+                _ => panic!("unsupported instruction must be rejected"),
+            }
+        }
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn evict_expired_dependents() {
+        let parent = parent_tx(0, 10);
+        let child = child_tx(&parent, 100);
+        let mut pool = Mempool::new();
+        let parent_id = pool.add(parent, 0).unwrap();
+        let child_id = pool.add(child, 0).unwrap();
+        let other_id = pool.add(nonce_tx(3, 100), 0).unwrap();
+
+        assert_eq!(pool.evict_expired(10), Vec::new());
+        assert_eq!(pool.evict_expired(50), vec![parent_id, child_id]);
+        assert_eq!(pool.len(), 1);
+        assert!(pool.contains(&other_id));
+
+        // Evicted nonces and inputs are free again.
+        assert!(pool.add(parent_tx(0, 10), 0).is_ok());
+    }
+
+    #[test]
+    fn candidate_block_order() {
+        let parent = parent_tx(0, 100);
+        let child = child_tx(&parent, 100);
+        let parent_program = parent.program.clone();
+        let child_program = child.program.clone();
+
+        // Child is admitted first, but must follow its parent in the block.
+        let mut pool = Mempool::new();
+        pool.add(child, 0).unwrap();
+        pool.add(parent, 0).unwrap();
+
+        let block = pool.candidate_block(0);
+        assert_eq!(block.len(), 2);
+        assert_eq!(block[0].program, parent_program);
+        assert_eq!(block[1].program, child_program);
+    }
+
+    #[test]
+    fn remove_promotes_children() {
+        // Parent is not valid yet, so its child cannot be included either.
+        let parent = parent_tx(20, 100);
+        let child = child_tx(&parent, 100);
+        let child_program = child.program.clone();
+        let mut pool = Mempool::new();
+        let parent_id = pool.add(parent, 0).unwrap();
+        pool.add(child, 0).unwrap();
+        assert!(pool.candidate_block(10).is_empty());
+
+        // Once the parent is included elsewhere, the child has no parents in the pool.
+        assert!(pool.remove(&parent_id).is_some());
+        assert!(pool.remove(&parent_id).is_none());
+        let block = pool.candidate_block(10);
+        assert_eq!(block.len(), 1);
+        assert_eq!(block[0].program, child_program);
+    }
+}
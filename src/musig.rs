@@ -0,0 +1,514 @@
+//! Interactive multi-party protocol for creating aggregated signatures.
+//!
+//! Each party holds the private key for one of the aggregated verification keys.
+//! The parties exchange three rounds of messages: nonce precommitments, nonce commitments
+//! and signature shares. Then each party assembles the same `Signature`
+//! that verifies with `Signature::verify_aggregated` for the same transcript and keys,
+//! or, if started with `Party::new_aggregated`, as a signature of their combined key.
+//! Messages are ordered by the position of the parties' keys in the aggregated list.
+#![allow(non_snake_case)]
+
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+
+use crate::errors::MuSigError;
+use crate::gens;
+use crate::signature::{self, Signature, VerificationKey};
+use crate::transcript::TranscriptProtocol;
+
+/// Entry point to the signing protocol.
+pub struct Party;
+
+/// Party that has sent its nonce precommitment.
+pub struct PartyAwaitingPrecommitments {
+    session: Session,
+    r: Scalar,
+    R: RistrettoPoint,
+}
+
+/// Party that has sent its nonce commitment.
+pub struct PartyAwaitingCommitments {
+    session: Session,
+    r: Scalar,
+    R: RistrettoPoint,
+    precommitments: Vec<NoncePrecommitment>,
+}
+
+/// Party that has sent its signature share.
+pub struct PartyAwaitingShares {
+    session: Session,
+    e: Scalar,
+    R: RistrettoPoint,
+    nonces: Vec<RistrettoPoint>,
+}
+
+/// Hash of a party's nonce commitment, sent in the first round.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NoncePrecommitment([u8; 32]);
+
+/// Party's nonce commitment, sent in the second round.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NonceCommitment(CompressedRistretto);
+
+/// Party's share of the signature, sent in the third round.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Share(Scalar);
+
+/// State shared by all rounds of the protocol.
+struct Session {
+    transcript: Transcript,
+    privkey: Scalar,
+    position: usize,
+    pubkeys: Vec<RistrettoPoint>,
+    /// Weights of the keys in the signed key.
+    x: Vec<Scalar>,
+}
+
+impl Party {
+    /// Starts the signing protocol for the private key `privkey`, whose verification key is among `pubkeys`.
+    /// The transcript must be in the same state as the one passed to `Signature::verify_aggregated`.
+    /// Returns the party state and the precommitment to send to the other parties.
+    pub fn new(
+        mut transcript: Transcript,
+        privkey: Scalar,
+        pubkeys: &[VerificationKey],
+    ) -> Result<(PartyAwaitingPrecommitments, NoncePrecommitment), MuSigError> {
+        let (position, points) = Party::locate(&privkey, pubkeys)?;
+
+        // Same weights as in `Signature::verify_aggregated`.
+        let x = signature::key_weights(&mut transcript, pubkeys);
+        let (party, precommitment) = Party::with_weights(transcript, privkey, position, points, x);
+        Ok((party, precommitment))
+    }
+
+    /// Starts the signing protocol for the combined key `VerificationKey::aggregate(pubkeys)`,
+    /// where `privkey` is the private key of one of `pubkeys`.
+    /// The signature verifies as a signature of the combined key alone,
+    /// e.g. with `Signature::verify_single` or by `signtx` for a contract locked to that key.
+    pub fn new_aggregated(
+        mut transcript: Transcript,
+        privkey: Scalar,
+        pubkeys: &[VerificationKey],
+    ) -> Result<(PartyAwaitingPrecommitments, NoncePrecommitment), MuSigError> {
+        let (position, points) = Party::locate(&privkey, pubkeys)?;
+        let weights = signature::aggregation_weights(pubkeys);
+        let combined: RistrettoPoint = weights.iter().zip(points.iter()).map(|(a, p)| a * p).sum();
+
+        // Same weight of the combined key as in `Signature::verify_single`,
+        // applied to the weight of each key within the combined key.
+        let x = signature::key_weights(&mut transcript, &[VerificationKey(combined.compress())])[0];
+        let x = weights.into_iter().map(|a| x * a).collect();
+        let (party, precommitment) = Party::with_weights(transcript, privkey, position, points, x);
+        Ok((party, precommitment))
+    }
+
+    /// Finds the position of the signer's key and decodes all the keys.
+    fn locate(
+        privkey: &Scalar,
+        pubkeys: &[VerificationKey],
+    ) -> Result<(usize, Vec<RistrettoPoint>), MuSigError> {
+        let own_key = VerificationKey::from_secret(privkey);
+        let position = pubkeys
+            .iter()
+            .position(|k| *k == own_key)
+            .ok_or(MuSigError::UnknownSigner)?;
+        let points = pubkeys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| k.0.decompress().ok_or(MuSigError::InvalidKey(i)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((position, points))
+    }
+
+    /// Starts the signing protocol for a signature verified as `s*B == e*Σ x_i*P_i + R`,
+    /// where the party at `position` holds the private key of `pubkeys[position]`.
+    /// The signed keys must already be committed to the transcript.
+    pub(crate) fn with_weights(
+        transcript: Transcript,
+        privkey: Scalar,
+        position: usize,
+        pubkeys: Vec<RistrettoPoint>,
+        x: Vec<Scalar>,
+    ) -> (PartyAwaitingPrecommitments, NoncePrecommitment) {
+        let session = Session {
+            transcript,
+            privkey,
+            position,
+            pubkeys,
+            x,
+        };
+
+        // Generate secret nonce
+        let mut rng = session
+            .transcript
+            .build_rng()
+            .commit_witness_bytes(b"privkey", privkey.as_bytes())
+            .finalize(&mut rand::thread_rng());
+        let r = Scalar::random(&mut rng);
+        let R = r * gens::pedersen_gens().B;
+
+        let precommitment = NoncePrecommitment::new(&R.compress());
+        (PartyAwaitingPrecommitments { session, r, R }, precommitment)
+    }
+}
+
+impl PartyAwaitingPrecommitments {
+    /// Receives the precommitments of all parties, including the party's own one at its position.
+    /// Returns the nonce commitment to send to the other parties.
+    pub fn receive_precommitments(
+        self,
+        precommitments: Vec<NoncePrecommitment>,
+    ) -> Result<(PartyAwaitingCommitments, NonceCommitment), MuSigError> {
+        self.session.check_count(precommitments.len())?;
+        let commitment = NonceCommitment(self.R.compress());
+        if precommitments[self.session.position] != NoncePrecommitment::new(&commitment.0) {
+            return Err(MuSigError::OwnMessageMismatch);
+        }
+        let party = PartyAwaitingCommitments {
+            session: self.session,
+            r: self.r,
+            R: self.R,
+            precommitments,
+        };
+        Ok((party, commitment))
+    }
+}
+
+impl PartyAwaitingCommitments {
+    /// Receives the nonce commitments of all parties and checks them against their precommitments.
+    /// Returns the signature share to send to the other parties.
+    pub fn receive_commitments(
+        mut self,
+        commitments: Vec<NonceCommitment>,
+    ) -> Result<(PartyAwaitingShares, Share), MuSigError> {
+        self.session.check_count(commitments.len())?;
+        if commitments[self.session.position].0 != self.R.compress() {
+            return Err(MuSigError::OwnMessageMismatch);
+        }
+        let nonces = commitments
+            .iter()
+            .zip(self.precommitments.iter())
+            .enumerate()
+            .map(|(i, (commitment, precommitment))| {
+                if NoncePrecommitment::new(&commitment.0) != *precommitment {
+                    return Err(MuSigError::NonceMismatch(i));
+                }
+                commitment
+                    .0
+                    .decompress()
+                    .ok_or(MuSigError::NonceMismatch(i))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let R: RistrettoPoint = nonces.iter().sum();
+        self.session.transcript.commit_point(b"R", &R.compress());
+        let e = self.session.transcript.challenge_scalar(b"e");
+
+        let position = self.session.position;
+        let share = Share(self.r + e * self.session.x[position] * self.session.privkey);
+        let party = PartyAwaitingShares {
+            session: self.session,
+            e,
+            R,
+            nonces,
+        };
+        Ok((party, share))
+    }
+}
+
+impl PartyAwaitingShares {
+    /// Receives the signature shares of all parties, checks them and returns the aggregated signature.
+    pub fn receive_shares(self, shares: Vec<Share>) -> Result<Signature, MuSigError> {
+        self.session.check_count(shares.len())?;
+        let B = gens::pedersen_gens().B;
+        for (i, share) in shares.iter().enumerate() {
+            // s_i*B == R_i + e*x_i*P_i
+            let expected = self.nonces[i] + self.e * self.session.x[i] * self.session.pubkeys[i];
+            if share.0 * B != expected {
+                return Err(MuSigError::InvalidShare(i));
+            }
+        }
+        Ok(Signature {
+            R: self.R.compress(),
+            s: shares.iter().map(|share| share.0).sum(),
+        })
+    }
+}
+
+impl Session {
+    fn check_count(&self, actual: usize) -> Result<(), MuSigError> {
+        if actual != self.pubkeys.len() {
+            return Err(MuSigError::WrongNumberOfMessages {
+                expected: self.pubkeys.len(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl NoncePrecommitment {
+    fn new(commitment: &CompressedRistretto) -> Self {
+        let mut t = Transcript::new(b"ZkVM.musig");
+        t.commit_point(b"R", commitment);
+        let mut buf = [0u8; 32];
+        t.challenge_bytes(b"precommitment", &mut buf);
+        NoncePrecommitment(buf)
+    }
+
+    /// Encodes the precommitment as a 32-byte array.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    /// Decodes the precommitment from a 32-byte array.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        NoncePrecommitment(bytes)
+    }
+}
+
+impl NonceCommitment {
+    /// Encodes the nonce commitment as a 32-byte array.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    /// Decodes the nonce commitment from a 32-byte array.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        NonceCommitment(CompressedRistretto(bytes))
+    }
+}
+
+impl Share {
+    /// Encodes the share as a 32-byte array.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    /// Decodes the share from a 32-byte array.
+    pub fn from_bytes(bytes: [u8; 32]) -> Result<Self, MuSigError> {
+        Scalar::from_canonical_bytes(bytes)
+            .map(Share)
+            .ok_or(MuSigError::FormatError)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn transcript() -> Transcript {
+        let mut t = Transcript::new(b"ZkVM.signtx");
+        t.commit_bytes(b"txid", &[7u8; 32]);
+        t
+    }
+
+    /// Runs the protocol among parties started with `start`, passing serialized messages.
+    /// `tamper` can modify the serialized shares before they are delivered.
+    pub(crate) fn sign<S, F>(
+        privkeys: &[Scalar],
+        start: S,
+        tamper: F,
+    ) -> Vec<Result<Signature, MuSigError>>
+    where
+        S: Fn(
+            Scalar,
+            &[VerificationKey],
+        ) -> Result<(PartyAwaitingPrecommitments, NoncePrecommitment), MuSigError>,
+        F: Fn(&mut Vec<[u8; 32]>),
+    {
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+
+        let (parties, precommitments): (Vec<_>, Vec<_>) = privkeys
+            .iter()
+            .map(|p| start(*p, &pubkeys).unwrap())
+            .unzip();
+        let precommitments = precommitments
+            .iter()
+            .map(|m| m.to_bytes())
+            .collect::<Vec<_>>();
+
+        let (parties, commitments): (Vec<_>, Vec<_>) = parties
+            .into_iter()
+            .map(|p| {
+                let received = precommitments
+                    .iter()
+                    .map(|m| NoncePrecommitment::from_bytes(*m))
+                    .collect();
+                p.receive_precommitments(received).unwrap()
+            })
+            .unzip();
+        let commitments = commitments.iter().map(|m| m.to_bytes()).collect::<Vec<_>>();
+
+        let (parties, shares): (Vec<_>, Vec<_>) = parties
+            .into_iter()
+            .map(|p| {
+                let received = commitments
+                    .iter()
+                    .map(|m| NonceCommitment::from_bytes(*m))
+                    .collect();
+                p.receive_commitments(received).unwrap()
+            })
+            .unzip();
+        let mut shares = shares.iter().map(|m| m.to_bytes()).collect::<Vec<_>>();
+        tamper(&mut shares);
+
+        parties
+            .into_iter()
+            .map(|p| {
+                let received = shares
+                    .iter()
+                    .map(|m| Share::from_bytes(*m).unwrap())
+                    .collect();
+                p.receive_shares(received)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn aggregated_signature() {
+        let privkeys = (0..3)
+            .map(|_| Scalar::random(&mut rand::thread_rng()))
+            .collect::<Vec<_>>();
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+
+        let signatures = sign(
+            &privkeys,
+            |p, keys| Party::new(transcript(), p, keys),
+            |_| {},
+        );
+        for sig in signatures.iter() {
+            let sig = sig.as_ref().unwrap();
+            assert_eq!(
+                sig.to_bytes()[..],
+                signatures[0].as_ref().unwrap().to_bytes()[..]
+            );
+            let op = sig.verify_aggregated(&mut transcript(), &pubkeys);
+            assert!(op.verify().is_ok());
+        }
+    }
+
+    #[test]
+    fn combined_key_signature() {
+        let privkeys = (0..3)
+            .map(|_| Scalar::random(&mut rand::thread_rng()))
+            .collect::<Vec<_>>();
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+        let combined = VerificationKey::aggregate(&pubkeys).unwrap();
+
+        let signatures = sign(
+            &privkeys,
+            |p, keys| Party::new_aggregated(transcript(), p, keys),
+            |_| {},
+        );
+        for sig in signatures.into_iter() {
+            let sig = sig.unwrap();
+            assert!(sig
+                .verify_single(&mut transcript(), combined)
+                .verify()
+                .is_ok());
+            assert!(sig
+                .verify_aggregated(&mut transcript(), &pubkeys)
+                .verify()
+                .is_err());
+        }
+
+        // Holder of all the private keys can sign for the combined key alone.
+        let single = Signature::sign_single(
+            &mut transcript(),
+            VerificationKey::aggregate_secret(&privkeys),
+        );
+        assert!(single
+            .verify_single(&mut transcript(), combined)
+            .verify()
+            .is_ok());
+    }
+
+    #[test]
+    fn invalid_share() {
+        let privkeys = (0..3)
+            .map(|_| Scalar::random(&mut rand::thread_rng()))
+            .collect::<Vec<_>>();
+        let signatures = sign(
+            &privkeys,
+            |p, keys| Party::new(transcript(), p, keys),
+            |shares| shares[1] = Scalar::one().to_bytes(),
+        );
+        for sig in signatures.into_iter() {
+            assert_eq!(sig.unwrap_err(), MuSigError::InvalidShare(1));
+        }
+    }
+
+    #[test]
+    fn nonce_mismatch() {
+        let privkey = Scalar::random(&mut rand::thread_rng());
+        let other = Scalar::random(&mut rand::thread_rng());
+        let pubkeys = vec![
+            VerificationKey::from_secret(&privkey),
+            VerificationKey::from_secret(&other),
+        ];
+        let (party, precommitment) = Party::new(transcript(), privkey, &pubkeys).unwrap();
+        let (_, other_precommitment) = Party::new(transcript(), other, &pubkeys).unwrap();
+        let (party, commitment) = party
+            .receive_precommitments(vec![precommitment, other_precommitment])
+            .unwrap();
+        let forged = NonceCommitment(gens::pedersen_gens().B.compress());
+        assert_eq!(
+            party.receive_commitments(vec![commitment, forged]).err(),
+            Some(MuSigError::NonceMismatch(1))
+        );
+
+        let stranger = Scalar::random(&mut rand::thread_rng());
+        assert_eq!(
+            Party::new(transcript(), stranger, &pubkeys).err(),
+            Some(MuSigError::UnknownSigner)
+        );
+    }
+
+    #[test]
+    fn own_message_mismatch() {
+        let privkeys = (0..2)
+            .map(|_| Scalar::random(&mut rand::thread_rng()))
+            .collect::<Vec<_>>();
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+        let start = || {
+            privkeys
+                .iter()
+                .map(|p| Party::new(transcript(), *p, &pubkeys).unwrap())
+                .unzip::<_, _, Vec<_>, Vec<_>>()
+        };
+
+        // Coordinator replaces the precommitment of party 0 with the one of party 1.
+        let (mut parties, precommitments) = start();
+        let swapped = vec![precommitments[1], precommitments[1]];
+        assert_eq!(
+            parties.remove(0).receive_precommitments(swapped).err(),
+            Some(MuSigError::OwnMessageMismatch)
+        );
+
+        // Coordinator replaces the nonce commitment of party 1 with the one of party 0.
+        let (parties, precommitments) = start();
+        let (mut parties, commitments): (Vec<_>, Vec<_>) = parties
+            .into_iter()
+            .map(|p| p.receive_precommitments(precommitments.clone()).unwrap())
+            .unzip();
+        let swapped = vec![commitments[0], commitments[0]];
+        assert_eq!(
+            parties.remove(1).receive_commitments(swapped).err(),
+            Some(MuSigError::OwnMessageMismatch)
+        );
+    }
+}
@@ -1,6 +1,9 @@
 use byteorder::{ByteOrder, LittleEndian};
+use core::fmt;
 use core::mem;
 
+use crate::encoding;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Instruction {
     Push(usize), // size of the string
@@ -101,6 +104,57 @@ impl Opcode {
             unsafe { mem::transmute(code) }
         }
     }
+
+    /// Returns the name of the instruction as used in the specification.
+    pub fn name(self) -> &'static str {
+        match self {
+            Opcode::Push => "push",
+            Opcode::Drop => "drop",
+            Opcode::Dup => "dup",
+            Opcode::Roll => "roll",
+            Opcode::Const => "const",
+            Opcode::Var => "var",
+            Opcode::Alloc => "alloc",
+            Opcode::Mintime => "mintime",
+            Opcode::Maxtime => "maxtime",
+            Opcode::Neg => "neg",
+            Opcode::Add => "add",
+            Opcode::Mul => "mul",
+            Opcode::Eq => "eq",
+            Opcode::Range => "range",
+            Opcode::And => "and",
+            Opcode::Or => "or",
+            Opcode::Verify => "verify",
+            Opcode::Blind => "blind",
+            Opcode::Reblind => "reblind",
+            Opcode::Unblind => "unblind",
+            Opcode::Issue => "issue",
+            Opcode::Borrow => "borrow",
+            Opcode::Retire => "retire",
+            Opcode::Qty => "qty",
+            Opcode::Flavor => "flavor",
+            Opcode::Cloak => "cloak",
+            Opcode::Import => "import",
+            Opcode::Export => "export",
+            Opcode::Input => "input",
+            Opcode::Output => "output",
+            Opcode::Contract => "contract",
+            Opcode::Nonce => "nonce",
+            Opcode::Log => "log",
+            Opcode::Signtx => "signtx",
+            Opcode::Call => "call",
+            Opcode::Left => "left",
+            Opcode::Right => "right",
+            Opcode::Delegate => "delegate",
+        }
+    }
+
+    /// Finds an opcode by the name of its instruction.
+    pub fn from_name(name: &str) -> Option<Opcode> {
+        (0..=MAX_OPCODE)
+            .filter_map(Opcode::from_u8)
+            .find(|op| op.name() == name)
+    }
 }
 
 impl Instruction {
@@ -208,4 +262,139 @@ impl Instruction {
             Opcode::Delegate => Some((Instruction::Delegate, 1)),
         }
     }
+
+    /// Encodes the instruction with its immediate data.
+    /// For `Push` only the opcode and the length prefix are written,
+    /// the caller is expected to append the string itself.
+    pub fn encode(&self, program: &mut Vec<u8>) {
+        let write_op = |op: Opcode, program: &mut Vec<u8>| encoding::write_u8(op.to_u8(), program);
+        match self {
+            Instruction::Push(len) => {
+                write_op(Opcode::Push, program);
+                encoding::write_u32(*len as u32, program);
+            }
+            Instruction::Drop => write_op(Opcode::Drop, program),
+            Instruction::Dup(idx) => {
+                write_op(Opcode::Dup, program);
+                encoding::write_u32(*idx as u32, program);
+            }
+            Instruction::Roll(idx) => {
+                write_op(Opcode::Roll, program);
+                encoding::write_u32(*idx as u32, program);
+            }
+            Instruction::Const => write_op(Opcode::Const, program),
+            Instruction::Var => write_op(Opcode::Var, program),
+            Instruction::Alloc => write_op(Opcode::Alloc, program),
+            Instruction::Mintime => write_op(Opcode::Mintime, program),
+            Instruction::Maxtime => write_op(Opcode::Maxtime, program),
+            Instruction::Neg => write_op(Opcode::Neg, program),
+            Instruction::Add => write_op(Opcode::Add, program),
+            Instruction::Mul => write_op(Opcode::Mul, program),
+            Instruction::Eq => write_op(Opcode::Eq, program),
+            Instruction::Range(n) => {
+                write_op(Opcode::Range, program);
+                encoding::write_u8(*n, program);
+            }
+            Instruction::And => write_op(Opcode::And, program),
+            Instruction::Or => write_op(Opcode::Or, program),
+            Instruction::Verify => write_op(Opcode::Verify, program),
+            Instruction::Blind => write_op(Opcode::Blind, program),
+            Instruction::Reblind => write_op(Opcode::Reblind, program),
+            Instruction::Unblind => write_op(Opcode::Unblind, program),
+            Instruction::Issue => write_op(Opcode::Issue, program),
+            Instruction::Borrow => write_op(Opcode::Borrow, program),
+            Instruction::Retire => write_op(Opcode::Retire, program),
+            Instruction::Qty => write_op(Opcode::Qty, program),
+            Instruction::Flavor => write_op(Opcode::Flavor, program),
+            Instruction::Cloak(m, n) => {
+                write_op(Opcode::Cloak, program);
+                encoding::write_u32(*m as u32, program);
+                encoding::write_u32(*n as u32, program);
+            }
+            Instruction::Import => write_op(Opcode::Import, program),
+            Instruction::Export => write_op(Opcode::Export, program),
+            Instruction::Input => write_op(Opcode::Input, program),
+            Instruction::Output(k) => {
+                write_op(Opcode::Output, program);
+                encoding::write_u32(*k as u32, program);
+            }
+            Instruction::Contract(k) => {
+                write_op(Opcode::Contract, program);
+                encoding::write_u32(*k as u32, program);
+            }
+            Instruction::Nonce => write_op(Opcode::Nonce, program),
+            Instruction::Log => write_op(Opcode::Log, program),
+            Instruction::Signtx => write_op(Opcode::Signtx, program),
+            Instruction::Call => write_op(Opcode::Call, program),
+            Instruction::Left => write_op(Opcode::Left, program),
+            Instruction::Right => write_op(Opcode::Right, program),
+            Instruction::Delegate => write_op(Opcode::Delegate, program),
+            Instruction::Ext(code) => encoding::write_u8(*code, program),
+        }
+    }
+}
+
+impl Instruction {
+    /// Returns the opcode of the instruction, or `None` for the extension opcodes.
+    pub fn opcode(&self) -> Option<Opcode> {
+        match self {
+            Instruction::Push(_) => Some(Opcode::Push),
+            Instruction::Drop => Some(Opcode::Drop),
+            Instruction::Dup(_) => Some(Opcode::Dup),
+            Instruction::Roll(_) => Some(Opcode::Roll),
+            Instruction::Const => Some(Opcode::Const),
+            Instruction::Var => Some(Opcode::Var),
+            Instruction::Alloc => Some(Opcode::Alloc),
+            Instruction::Mintime => Some(Opcode::Mintime),
+            Instruction::Maxtime => Some(Opcode::Maxtime),
+            Instruction::Neg => Some(Opcode::Neg),
+            Instruction::Add => Some(Opcode::Add),
+            Instruction::Mul => Some(Opcode::Mul),
+            Instruction::Eq => Some(Opcode::Eq),
+            Instruction::Range(_) => Some(Opcode::Range),
+            Instruction::And => Some(Opcode::And),
+            Instruction::Or => Some(Opcode::Or),
+            Instruction::Verify => Some(Opcode::Verify),
+            Instruction::Blind => Some(Opcode::Blind),
+            Instruction::Reblind => Some(Opcode::Reblind),
+            Instruction::Unblind => Some(Opcode::Unblind),
+            Instruction::Issue => Some(Opcode::Issue),
+            Instruction::Borrow => Some(Opcode::Borrow),
+            Instruction::Retire => Some(Opcode::Retire),
+            Instruction::Qty => Some(Opcode::Qty),
+            Instruction::Flavor => Some(Opcode::Flavor),
+            Instruction::Cloak(_, _) => Some(Opcode::Cloak),
+            Instruction::Import => Some(Opcode::Import),
+            Instruction::Export => Some(Opcode::Export),
+            Instruction::Input => Some(Opcode::Input),
+            Instruction::Output(_) => Some(Opcode::Output),
+            Instruction::Contract(_) => Some(Opcode::Contract),
+            Instruction::Nonce => Some(Opcode::Nonce),
+            Instruction::Log => Some(Opcode::Log),
+            Instruction::Signtx => Some(Opcode::Signtx),
+            Instruction::Call => Some(Opcode::Call),
+            Instruction::Left => Some(Opcode::Left),
+            Instruction::Right => Some(Opcode::Right),
+            Instruction::Delegate => Some(Opcode::Delegate),
+            Instruction::Ext(_) => None,
+        }
+    }
+}
+
+/// Formats the instruction in the assembler notation, e.g. `cloak:3:2`.
+/// Strings pushed by `push` are not part of the instruction and are not displayed.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Push(len) => write!(f, "push:{}", len),
+            Instruction::Dup(i) => write!(f, "dup:{}", i),
+            Instruction::Roll(i) => write!(f, "roll:{}", i),
+            Instruction::Range(n) => write!(f, "range:{}", n),
+            Instruction::Cloak(m, n) => write!(f, "cloak:{}:{}", m, n),
+            Instruction::Output(k) => write!(f, "output:{}", k),
+            Instruction::Contract(k) => write!(f, "contract:{}", k),
+            Instruction::Ext(code) => write!(f, "ext:{}", code),
+            _ => write!(f, "{}", self.opcode().map(|op| op.name()).unwrap_or("ext")),
+        }
+    }
 }
@@ -1,9 +1,11 @@
-use bulletproofs::PedersenGens;
 use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
 use curve25519_dalek::scalar::Scalar;
 use curve25519_dalek::traits::{IsIdentity, VartimeMultiscalarMul};
 
+use core::fmt;
+
 use super::errors::VMError;
+use crate::gens;
 
 /// Deferred point operation.
 #[derive(Clone, Debug)]
@@ -20,10 +22,31 @@ pub struct PointOp {
     pub arbitrary: Vec<(Scalar, CompressedRistretto)>,
 }
 
+/// Statement that a deferred point operation checks,
+/// with the transaction or instruction that deferred it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PointOpOrigin {
+    /// Aggregated signature of the txid by the `signtx` keys
+    /// of the transaction at the given index in the verified batch (0 for a single transaction).
+    Signtx(usize),
+
+    /// Flavor commitment check by `issue`.
+    IssueFlavor(usize),
+}
+
+impl fmt::Display for PointOpOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PointOpOrigin::Signtx(index) => write!(f, "signature of transaction {}", index),
+            PointOpOrigin::IssueFlavor(offset) => write!(f, "issue flavor at offset {}", offset),
+        }
+    }
+}
+
 impl PointOp {
     /// Non-batched verification of an individual point operation.
     pub fn verify(self) -> Result<(), VMError> {
-        let gens = PedersenGens::default();
+        let gens = gens::pedersen_gens();
         let (mut weights, points): (Vec<_>, Vec<_>) = self.arbitrary.into_iter().unzip();
         let mut points: Vec<_> = points.into_iter().map(|p| p.decompress()).collect();
 
@@ -50,9 +73,15 @@ impl PointOp {
         Ok(())
     }
 
+    /// Verifies the operations one by one and returns the index of the first failing one.
+    /// Used to locate the failure after the batch verification failed.
+    pub fn find_failure(batch: &[PointOp]) -> Option<usize> {
+        batch.iter().position(|op| op.clone().verify().is_err())
+    }
+
     /// Verifies a batch of point operations using one multi-scalar multiplication
     pub fn verify_batch(batch: &[PointOp]) -> Result<(), VMError> {
-        let gens = PedersenGens::default();
+        let gens = gens::pedersen_gens();
 
         // Get the total number of points in batch
         let dyn_length: usize = batch.iter().map(|p| p.arbitrary.len()).sum();
@@ -103,6 +132,7 @@ impl PointOp {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bulletproofs::PedersenGens;
 
     #[test]
     fn empty() {
@@ -210,4 +240,23 @@ mod tests {
         };
         assert!(PointOp::verify_batch(&[op1, op2]).is_ok());
     }
+
+    #[test]
+    fn find_failure() {
+        let gens = PedersenGens::default();
+        let valid = PointOp {
+            primary: Some(Scalar::one()),
+            secondary: None,
+            arbitrary: vec![(-Scalar::one(), gens.B.compress())],
+        };
+        let invalid = PointOp {
+            primary: Some(Scalar::one()),
+            secondary: None,
+            arbitrary: vec![(Scalar::one(), gens.B.compress())],
+        };
+        let batch = vec![valid.clone(), invalid, valid.clone()];
+        assert!(PointOp::verify_batch(&batch).is_err());
+        assert_eq!(PointOp::find_failure(&batch), Some(1));
+        assert_eq!(PointOp::find_failure(&[valid]), None);
+    }
 }
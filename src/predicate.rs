@@ -3,12 +3,14 @@
 //! Operations:
 //! - disjunction: P = L + f(L,R)*B
 //! - program_commitment: P = h(prog)*B2
-use bulletproofs::PedersenGens;
+//! - blinding: P = h(blinding)*B2, a point that can be neither signed for nor called
 use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
 
 use crate::errors::VMError;
+use crate::gens;
+use crate::ops::Instruction;
 use crate::point_ops::PointOp;
 use crate::transcript::TranscriptProtocol;
 
@@ -20,7 +22,7 @@ impl Predicate {
     /// Computes a disjunction of two predicates.
     pub fn or(&self, right: &Predicate) -> Result<Predicate, VMError> {
         let mut t = Transcript::new(b"ZkVM.predicate");
-        let gens = PedersenGens::default();
+        let gens = gens::pedersen_gens();
         t.commit_point(b"L", &self.0);
         t.commit_point(b"R", &right.0);
         let f = t.challenge_scalar(b"f");
@@ -48,7 +50,7 @@ impl Predicate {
     /// One cannot sign for it as a public key because it’s using a secondary generator.
     pub fn program_predicate(prog: &[u8]) -> Predicate {
         let mut t = Transcript::new(b"ZkVM.predicate");
-        let gens = PedersenGens::default();
+        let gens = gens::pedersen_gens();
         t.commit_bytes(b"prog", &prog);
         let h = t.challenge_scalar(b"h");
         Predicate((h * gens.B_blinding).compress())
@@ -70,9 +72,52 @@ impl Predicate {
     }
 }
 
+// Blinding
+impl Predicate {
+    /// Prepends `push:blinding drop` to the program, so its predicate cannot be guessed
+    /// without knowing the blinding factor. The blinded program runs the same way with `call`.
+    pub fn blind_program(prog: &[u8], blinding: &[u8; 32]) -> Vec<u8> {
+        let mut blinded = Vec::with_capacity(prog.len() + 38);
+        Instruction::Push(blinding.len()).encode(&mut blinded);
+        blinded.extend_from_slice(blinding);
+        Instruction::Drop.encode(&mut blinded);
+        blinded.extend_from_slice(prog);
+        blinded
+    }
+
+    /// Creates a program-based predicate for the program blinded with `blind_program`.
+    pub fn blinded_program_predicate(prog: &[u8], blinding: &[u8; 32]) -> Predicate {
+        Predicate::program_predicate(&Predicate::blind_program(prog, blinding))
+    }
+
+    /// Verifies whether the current predicate is a commitment to a program `prog` blinded with `blinding`.
+    /// Returns a `PointOp` instance that can be verified in a batch with other operations.
+    pub fn prove_blinded_program_predicate(&self, prog: &[u8], blinding: &[u8; 32]) -> PointOp {
+        self.prove_program_predicate(&Predicate::blind_program(prog, blinding))
+    }
+
+    /// Creates a predicate that can be neither signed for nor satisfied with `call`.
+    /// Used as a decoy branch hiding the other branch of a disjunction.
+    pub fn blinding_predicate(blinding: &[u8; 32]) -> Predicate {
+        let mut t = Transcript::new(b"ZkVM.predicate");
+        let gens = gens::pedersen_gens();
+        t.commit_bytes(b"blinding", blinding);
+        let h = t.challenge_scalar(b"h");
+        Predicate((h * gens.B_blinding).compress())
+    }
+
+    /// Computes a disjunction of the current predicate with a blinding predicate,
+    /// so the current predicate is not revealed when a sibling branch is taken.
+    /// The current predicate is reached with the `left` instruction.
+    pub fn blind(&self, blinding: &[u8; 32]) -> Result<Predicate, VMError> {
+        self.or(&Predicate::blinding_predicate(blinding))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bulletproofs::PedersenGens;
 
     #[test]
     fn valid_program_commitment() {
@@ -129,4 +174,34 @@ mod tests {
         let op = pred.prove_or(&right, &left);
         assert!(op.verify().is_err());
     }
+
+    #[test]
+    fn blinded_program_commitment() {
+        let prog = b"iddqd";
+        let pred = Predicate::blinded_program_predicate(prog, &[1u8; 32]);
+        assert_ne!(pred, Predicate::program_predicate(prog));
+        assert_ne!(pred, Predicate::blinded_program_predicate(prog, &[2u8; 32]));
+        assert!(pred
+            .prove_blinded_program_predicate(prog, &[1u8; 32])
+            .verify()
+            .is_ok());
+        assert!(pred
+            .prove_blinded_program_predicate(prog, &[2u8; 32])
+            .verify()
+            .is_err());
+
+        let blinded = Predicate::blind_program(prog, &[1u8; 32]);
+        assert!(pred.prove_program_predicate(&blinded).verify().is_ok());
+    }
+
+    #[test]
+    fn blinded_key() {
+        let gens = PedersenGens::default();
+        let key = Predicate(gens.B.compress());
+        let blinding = [7u8; 32];
+
+        let pred = key.blind(&blinding).unwrap();
+        let op = pred.prove_or(&key, &Predicate::blinding_predicate(&blinding));
+        assert!(op.verify().is_ok());
+    }
 }
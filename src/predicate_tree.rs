@@ -0,0 +1,386 @@
+//! Builder of predicate trees out of keys, aggregated key sets and programs.
+//!
+//! Leaves are combined pairwise with `Predicate::or` into a balanced tree,
+//! so that any leaf is reachable with a logarithmic number of `left`/`right` instructions.
+use crate::errors::VMError;
+use crate::ops::Instruction;
+use crate::predicate::Predicate;
+use crate::signature::VerificationKey;
+
+/// Leaf of a predicate tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PredicateLeaf {
+    /// Single verification key.
+    Key(VerificationKey),
+
+    /// Set of keys combined with `VerificationKey::aggregate`,
+    /// whose holders must all sign together with `Party::new_aggregated`.
+    AggregatedKeys(Vec<VerificationKey>),
+
+    /// Program committed with `Predicate::program_predicate`.
+    Program(Vec<u8>),
+
+    /// Program committed with `Predicate::blinded_program_predicate`,
+    /// so it cannot be guessed from the predicate.
+    BlindedProgram(Vec<u8>, [u8; 32]),
+
+    /// Leaf combined with `Predicate::blind`, so it is not revealed when another leaf is taken.
+    /// Reaching the inner leaf takes an extra `left` instruction.
+    Blinded(Box<PredicateLeaf>, [u8; 32]),
+}
+
+/// Balanced tree of predicates.
+#[derive(Clone, Debug)]
+pub struct PredicateTree {
+    leaves: Vec<PredicateLeaf>,
+    /// Predicates of each level of the tree, from the leaves up to the root.
+    levels: Vec<Vec<Predicate>>,
+}
+
+/// Step from a node of the tree to one of its children.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Branch {
+    /// Left child of the node.
+    pub left: Predicate,
+
+    /// Right child of the node.
+    pub right: Predicate,
+
+    /// Instruction selecting the child: `left` or `right`.
+    pub instruction: Instruction,
+}
+
+impl PredicateLeaf {
+    /// Computes the predicate of the leaf.
+    pub fn predicate(&self) -> Result<Predicate, VMError> {
+        match self {
+            PredicateLeaf::Key(key) => Ok(Predicate(key.0)),
+            PredicateLeaf::AggregatedKeys(keys) => {
+                Ok(Predicate(VerificationKey::aggregate(keys)?.0))
+            }
+            PredicateLeaf::Program(program) => Ok(Predicate::program_predicate(program)),
+            PredicateLeaf::BlindedProgram(program, blinding) => {
+                Ok(Predicate::blinded_program_predicate(program, blinding))
+            }
+            PredicateLeaf::Blinded(leaf, blinding) => leaf.predicate()?.blind(blinding),
+        }
+    }
+
+    /// Returns the program to pass to `call` for a program leaf, including the blinding.
+    pub fn program(&self) -> Option<Vec<u8>> {
+        match self {
+            PredicateLeaf::Program(program) => Some(program.clone()),
+            PredicateLeaf::BlindedProgram(program, blinding) => {
+                Some(Predicate::blind_program(program, blinding))
+            }
+            PredicateLeaf::Blinded(leaf, _) => leaf.program(),
+            _ => None,
+        }
+    }
+
+    /// Creates a leaf for every combination of `m` out of the given keys,
+    /// so that any `m` key holders can sign together with `Party::new_aggregated`.
+    /// Combinations are listed in lexicographic order of the key positions.
+    /// Fails if `m` is zero or exceeds the number of keys.
+    pub fn m_of_n(m: usize, keys: &[VerificationKey]) -> Result<Vec<PredicateLeaf>, VMError> {
+        if m == 0 || m > keys.len() {
+            return Err(VMError::FormatError);
+        }
+        let mut leaves = Vec::new();
+        let mut selected = Vec::with_capacity(m);
+        combinations(m, keys, 0, &mut selected, &mut leaves);
+        Ok(leaves)
+    }
+}
+
+fn combinations(
+    m: usize,
+    keys: &[VerificationKey],
+    start: usize,
+    selected: &mut Vec<VerificationKey>,
+    leaves: &mut Vec<PredicateLeaf>,
+) {
+    if selected.len() == m {
+        leaves.push(match selected.as_slice() {
+            [key] => PredicateLeaf::Key(*key),
+            keys => PredicateLeaf::AggregatedKeys(keys.to_vec()),
+        });
+        return;
+    }
+    for i in start..keys.len() {
+        selected.push(keys[i]);
+        combinations(m, keys, i + 1, selected, leaves);
+        selected.pop();
+    }
+}
+
+impl PredicateTree {
+    /// Builds a balanced tree of the leaves.
+    /// Fails if there are no leaves or if a leaf contains an invalid key.
+    pub fn new(leaves: Vec<PredicateLeaf>) -> Result<Self, VMError> {
+        if leaves.len() == 0 {
+            return Err(VMError::FormatError);
+        }
+        let mut levels = vec![leaves
+            .iter()
+            .map(|leaf| leaf.predicate())
+            .collect::<Result<Vec<_>, _>>()?];
+        while levels[levels.len() - 1].len() > 1 {
+            let next = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => left.or(right),
+                    // Odd node is carried up to the next level.
+                    [single] => Ok(*single),
+                    _ => unreachable!(),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            levels.push(next);
+        }
+        Ok(PredicateTree { leaves, levels })
+    }
+
+    /// Returns the root predicate of the tree.
+    pub fn root(&self) -> Predicate {
+        self.levels[self.levels.len() - 1][0]
+    }
+
+    /// Returns the leaves of the tree.
+    pub fn leaves(&self) -> &[PredicateLeaf] {
+        &self.leaves
+    }
+
+    /// Returns the branches leading from the root to the leaf at `index`, starting at the root.
+    /// For blinded leaves, the path continues to the inner leaf.
+    /// Returns `None` if the index is out of range.
+    pub fn path(&self, index: usize) -> Option<Vec<Branch>> {
+        if index >= self.leaves.len() {
+            return None;
+        }
+        let mut path = Vec::new();
+        let mut position = index;
+        for level in self.levels[..self.levels.len() - 1].iter() {
+            let sibling = position ^ 1;
+            if sibling < level.len() {
+                let (left, right, instruction) = if position % 2 == 0 {
+                    (position, sibling, Instruction::Left)
+                } else {
+                    (sibling, position, Instruction::Right)
+                };
+                path.push(Branch {
+                    left: level[left],
+                    right: level[right],
+                    instruction,
+                });
+            }
+            position /= 2;
+        }
+        path.reverse();
+
+        let mut leaf = &self.leaves[index];
+        while let PredicateLeaf::Blinded(inner, blinding) = leaf {
+            path.push(Branch {
+                left: inner.predicate().ok()?,
+                right: Predicate::blinding_predicate(blinding),
+                instruction: Instruction::Left,
+            });
+            leaf = inner;
+        }
+        Some(path)
+    }
+
+    /// Encodes the program that takes a contract locked to the root predicate
+    /// and leaves it locked to the predicate of the leaf at `index`, or of its inner leaf if it is blinded.
+    /// Returns `None` if the index is out of range.
+    pub fn path_program(&self, index: usize) -> Option<Vec<u8>> {
+        let mut program = Vec::new();
+        for branch in self.path(index)?.iter() {
+            branch.encode(&mut program);
+        }
+        Some(program)
+    }
+}
+
+impl Branch {
+    /// Encodes the branch as `push:L push:R left` or `push:L push:R right`.
+    pub fn encode(&self, program: &mut Vec<u8>) {
+        for predicate in [self.left, self.right].iter() {
+            Instruction::Push(32).encode(program);
+            program.extend_from_slice(predicate.0.as_bytes());
+        }
+        self.instruction.encode(program);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::disassemble;
+    use crate::musig::{self, Party};
+    use crate::vm::tests::{self as vm_tests, make_tx, make_tx_with, program, spend};
+    use crate::vm::VM;
+    use curve25519_dalek::scalar::Scalar;
+
+    fn keys(n: usize) -> Vec<VerificationKey> {
+        (0..n)
+            .map(|i| VerificationKey::from_secret(&Scalar::from(i as u64 + 1)))
+            .collect()
+    }
+
+    #[test]
+    fn m_of_n_leaves() {
+        let keys = keys(4);
+        let leaves = PredicateLeaf::m_of_n(2, &keys).unwrap();
+        assert_eq!(leaves.len(), 6);
+        assert_eq!(
+            leaves[0],
+            PredicateLeaf::AggregatedKeys(vec![keys[0], keys[1]])
+        );
+        assert_eq!(
+            leaves[5],
+            PredicateLeaf::AggregatedKeys(vec![keys[2], keys[3]])
+        );
+        assert_eq!(
+            PredicateLeaf::m_of_n(1, &keys).unwrap()[3],
+            PredicateLeaf::Key(keys[3])
+        );
+        assert_eq!(
+            PredicateLeaf::m_of_n(4, &keys).unwrap(),
+            vec![PredicateLeaf::AggregatedKeys(keys.clone())]
+        );
+        assert_eq!(
+            PredicateLeaf::m_of_n(5, &keys).err(),
+            Some(VMError::FormatError)
+        );
+        assert_eq!(
+            PredicateLeaf::m_of_n(0, &keys).err(),
+            Some(VMError::FormatError)
+        );
+    }
+
+    /// Spends an output locked to an M-of-N leaf with separate signers.
+    /// The VM does not support `left` and `right` yet, so the path from the root is checked
+    /// only with `Predicate::prove_or`, and the spent output is locked to the leaf predicate directly.
+    #[test]
+    fn m_of_n_spend() {
+        let keys = keys(3);
+        let tree = PredicateTree::new(PredicateLeaf::m_of_n(2, &keys).unwrap()).unwrap();
+
+        // Leaf of the first and the third key.
+        let leaf = &tree.leaves()[1];
+        assert_eq!(*leaf, PredicateLeaf::AggregatedKeys(vec![keys[0], keys[2]]));
+        let mut predicate = tree.root();
+        for branch in tree.path(1).unwrap() {
+            assert!(predicate
+                .prove_or(&branch.left, &branch.right)
+                .verify()
+                .is_ok());
+            predicate = match branch.instruction {
+                Instruction::Left => branch.left,
+                _ => branch.right,
+            };
+        }
+        assert_eq!(predicate, leaf.predicate().unwrap());
+
+        let prog = program(
+            "<k> nonce signtx 0x01 <leaf> output:1",
+            &[
+                ("k", vm_tests::key(4)),
+                ("leaf", predicate.0.to_bytes().to_vec()),
+            ],
+        );
+        let parent = make_tx(prog, 0, 100, &[4]);
+        let prog = program("<input> input signtx drop", &[("input", spend(&parent, 0))]);
+        let signers = vec![Scalar::from(1u64), Scalar::from(3u64)];
+        let tx = make_tx_with(prog, 0, 100, |transcript| {
+            let mut signatures = musig::tests::sign(
+                &signers,
+                |p, keys| Party::new_aggregated(transcript.clone(), p, keys),
+                |_| {},
+            );
+            signatures.remove(0).unwrap()
+        });
+        assert!(VM::verify_tx(&tx).is_ok());
+    }
+
+    #[test]
+    fn paths_reach_leaves() {
+        let mut leaves = PredicateLeaf::m_of_n(2, &keys(3)).unwrap();
+        leaves.push(PredicateLeaf::Program(b"iddqd".to_vec()));
+        leaves.push(PredicateLeaf::Key(keys(1)[0]));
+        leaves.push(PredicateLeaf::BlindedProgram(b"idkfa".to_vec(), [1u8; 32]));
+        leaves.push(PredicateLeaf::Blinded(
+            Box::new(PredicateLeaf::Key(keys(2)[1])),
+            [2u8; 32],
+        ));
+        let tree = PredicateTree::new(leaves).unwrap();
+
+        for (i, leaf) in tree.leaves().iter().enumerate() {
+            let leaf = match leaf {
+                PredicateLeaf::Blinded(inner, _) => inner,
+                _ => leaf,
+            };
+            let mut predicate = tree.root();
+            for branch in tree.path(i).unwrap() {
+                assert!(predicate
+                    .prove_or(&branch.left, &branch.right)
+                    .verify()
+                    .is_ok());
+                predicate = match branch.instruction {
+                    Instruction::Left => branch.left,
+                    _ => branch.right,
+                };
+            }
+            assert_eq!(predicate, leaf.predicate().unwrap());
+        }
+        assert_eq!(tree.path(7), None);
+        assert_eq!(
+            tree.path(6).unwrap().last().unwrap().left,
+            Predicate(keys(2)[1].0)
+        );
+    }
+
+    #[test]
+    fn path_program() {
+        let tree = PredicateTree::new(PredicateLeaf::m_of_n(1, &keys(3)).unwrap()).unwrap();
+        assert_eq!(tree.path(2).unwrap().len(), 1);
+
+        let program = disassemble(&tree.path_program(1).unwrap());
+        let lines = program.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[2].starts_with("left "));
+        assert!(lines[5].starts_with("right "));
+
+        let single = PredicateTree::new(vec![PredicateLeaf::Key(keys(1)[0])]).unwrap();
+        assert_eq!(single.root(), Predicate(keys(1)[0].0));
+        assert_eq!(single.path_program(0), Some(Vec::new()));
+        assert!(PredicateTree::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn blinded_leaves() {
+        let blinded = PredicateLeaf::BlindedProgram(b"iddqd".to_vec(), [1u8; 32]);
+        let program = blinded.program().unwrap();
+        let predicate = blinded.predicate().unwrap();
+        assert!(predicate.prove_program_predicate(&program).verify().is_ok());
+        assert_ne!(
+            predicate,
+            PredicateLeaf::Program(b"iddqd".to_vec())
+                .predicate()
+                .unwrap()
+        );
+
+        // Sibling of a blinded key reveals only the blinded predicate.
+        let key = PredicateLeaf::Key(keys(1)[0]);
+        let tree = PredicateTree::new(vec![
+            PredicateLeaf::Blinded(Box::new(key.clone()), [3u8; 32]),
+            PredicateLeaf::Program(b"iddqd".to_vec()),
+        ])
+        .unwrap();
+        let path = tree.path(1).unwrap();
+        assert_eq!(path.len(), 1);
+        assert_ne!(path[0].left, key.predicate().unwrap());
+        assert_eq!(tree.path(0).unwrap().len(), 2);
+        assert_eq!(key.program(), None);
+    }
+}
@@ -2,19 +2,20 @@
 
 #![allow(non_snake_case)]
 
-use bulletproofs::PedersenGens;
-use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
 
 use crate::errors::VMError;
+use crate::gens;
 use crate::point_ops::PointOp;
 use crate::transcript::TranscriptProtocol;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Signature {
-    R: CompressedRistretto,
-    s: Scalar,
+    pub(crate) R: CompressedRistretto,
+    pub(crate) s: Scalar,
 }
 
 impl Signature {
@@ -29,17 +30,9 @@ impl Signature {
         transcript: &mut Transcript,
         pubkeys: &[VerificationKey],
     ) -> PointOp {
-        transcript.commit_u64(b"n", pubkeys.len() as u64);
-        for p in pubkeys.iter() {
-            transcript.commit_point(b"P", &p.0);
-        }
-
-        let mut pairs = pubkeys
-            .iter()
-            .map(|p| {
-                let x = transcript.challenge_scalar(b"x");
-                (x, p.0)
-            })
+        let mut pairs = key_weights(transcript, pubkeys)
+            .into_iter()
+            .zip(pubkeys.iter().map(|p| p.0))
             .collect::<Vec<_>>();
 
         // Commit the signature's nonce commitment
@@ -66,6 +59,31 @@ impl Signature {
         }
     }
 
+    /// Verifies an aggregated signature for a collection of public keys.
+    /// Use `verify_aggregated` to verify the signature in a batch with other point operations.
+    pub fn verify(
+        &self,
+        transcript: &mut Transcript,
+        pubkeys: &[VerificationKey],
+    ) -> Result<(), VMError> {
+        self.verify_aggregated(transcript, pubkeys).verify()
+    }
+
+    /// Verifies a batch of aggregated signatures, each with its own transcript and public keys,
+    /// using one multi-scalar multiplication.
+    /// The transcripts must be in the same state as the ones used for signing.
+    pub fn verify_batch(
+        batch: &[(Transcript, Vec<VerificationKey>, Signature)],
+    ) -> Result<(), VMError> {
+        let ops = batch
+            .iter()
+            .map(|(transcript, pubkeys, sig)| {
+                sig.verify_aggregated(&mut transcript.clone(), pubkeys)
+            })
+            .collect::<Vec<_>>();
+        PointOp::verify_batch(&ops)
+    }
+
     /// Creates a signature for a single private key
     pub fn sign_single(transcript: &mut Transcript, privkey: Scalar) -> Self {
         Signature::sign_aggregated(transcript, &[privkey])
@@ -73,34 +91,47 @@ impl Signature {
 
     /// Creates an aggregated signature for a set of private keys
     pub fn sign_aggregated(transcript: &mut Transcript, privkeys: &[Scalar]) -> Self {
+        Signature::sign_aggregated_with_rng(transcript, privkeys, &mut rand::thread_rng())
+    }
+
+    /// Creates a deterministic signature for a single private key.
+    pub fn sign_single_deterministic(transcript: &mut Transcript, privkey: Scalar) -> Self {
+        Signature::sign_aggregated_deterministic(transcript, &[privkey])
+    }
+
+    /// Creates a deterministic aggregated signature for a set of private keys.
+    /// The nonce is derived only from the transcript and the private keys,
+    /// so signing the same message with the same keys always produces the same signature.
+    pub fn sign_aggregated_deterministic(transcript: &mut Transcript, privkeys: &[Scalar]) -> Self {
+        Signature::sign_aggregated_with_rng(transcript, privkeys, &mut NoEntropy)
+    }
+
+    /// Creates an aggregated signature for a set of private keys,
+    /// mixing the randomness from `rng` into the nonce derived from the transcript and the keys.
+    pub fn sign_aggregated_with_rng<T: RngCore + CryptoRng>(
+        transcript: &mut Transcript,
+        privkeys: &[Scalar],
+        rng: &mut T,
+    ) -> Self {
         // Derive public keys from privkeys
-        let gens = PedersenGens::default();
+        let gens = gens::pedersen_gens();
         let pubkeys = privkeys
             .iter()
             .map(|p| VerificationKey::from_secret(p))
             .collect::<Vec<_>>();
 
-        // Commit pubkeys
-        let n = pubkeys.len();
-        transcript.commit_u64(b"n", n as u64);
-        for p in pubkeys.iter() {
-            transcript.commit_point(b"P", &p.0);
-        }
-
         // Generate aggregated private key
-        let aggregated_privkey: Scalar = privkeys
-            .iter()
-            .map(|p| {
-                let x = transcript.challenge_scalar(b"x");
-                p * x
-            })
+        let aggregated_privkey: Scalar = key_weights(transcript, &pubkeys)
+            .into_iter()
+            .zip(privkeys.iter())
+            .map(|(x, p)| p * x)
             .sum();
 
         // Generate secret nonce
         let mut rng = transcript
             .build_rng()
             .commit_witness_bytes(b"privkey", aggregated_privkey.as_bytes())
-            .finalize(&mut rand::thread_rng());
+            .finalize(rng);
         let r = Scalar::random(&mut rng);
 
         // Commit the nonce to the transcript
@@ -138,15 +169,121 @@ impl Signature {
     }
 }
 
+/// RNG that contributes no randomness, making the transcript RNG a function of its inputs only.
+struct NoEntropy;
+
+impl RngCore for NoEntropy {
+    fn next_u32(&mut self) -> u32 {
+        0
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        0
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for byte in dest.iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for NoEntropy {}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct VerificationKey(pub CompressedRistretto);
 
 impl VerificationKey {
     // Constructs a VerificationKey from the private key.
     pub fn from_secret(privkey: &Scalar) -> Self {
-        let gens = PedersenGens::default();
+        let gens = gens::pedersen_gens();
         VerificationKey((privkey * gens.B).compress())
     }
+
+    /// Computes the combined key `Σ x_i·P_i` of a set of keys.
+    /// The weights `x_i` are derived like in `Signature::verify_aggregated`, but from a fixed
+    /// `ZkVM.aggregated-key` transcript instead of the signed message,
+    /// since the combined key must be known before any message is signed (e.g. to lock an output to it).
+    ///
+    /// This is therefore not the key that `signtx` or `Signature::verify_aggregated` computes
+    /// for the same keys: a signature accepted by `verify_aggregated(keys)` does not verify
+    /// for `aggregate(keys)`, and vice versa. Signatures for the combined key are made
+    /// with `Party::new_aggregated` or with `aggregate_secret`, and verified as single-key signatures.
+    /// Fails if any of the keys is not a valid point.
+    pub fn aggregate(pubkeys: &[VerificationKey]) -> Result<Self, VMError> {
+        let points = pubkeys
+            .iter()
+            .map(|p| p.0.decompress().ok_or(VMError::InvalidPoint))
+            .collect::<Result<Vec<_>, _>>()?;
+        let combined: RistrettoPoint = aggregation_weights(pubkeys)
+            .into_iter()
+            .zip(points.into_iter())
+            .map(|(x, p)| x * p)
+            .sum();
+        Ok(VerificationKey(combined.compress()))
+    }
+
+    /// Computes the private key for the combined key of `aggregate`
+    /// when all the private keys are held together, so it can sign as a single key.
+    pub fn aggregate_secret(privkeys: &[Scalar]) -> Scalar {
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+        aggregation_weights(&pubkeys)
+            .into_iter()
+            .zip(privkeys.iter())
+            .map(|(x, p)| p * x)
+            .sum()
+    }
+
+    /// Decodes a verification key from 32 bytes, checking that it is a valid point.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VMError> {
+        if bytes.len() != 32 {
+            return Err(VMError::FormatError);
+        }
+        let point = CompressedRistretto::from_slice(bytes);
+        point.decompress().ok_or(VMError::InvalidPoint)?;
+        Ok(VerificationKey(point))
+    }
+
+    /// Encodes the verification key as a 32-byte array.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    /// Decodes a verification key from a hex string, checking that it is a valid point.
+    pub fn from_hex(hex: &str) -> Result<Self, VMError> {
+        let bytes = hex::decode(hex).map_err(|_| VMError::FormatError)?;
+        VerificationKey::from_bytes(&bytes)
+    }
+
+    /// Encodes the verification key as a hex string.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0.as_bytes())
+    }
+}
+
+/// Commits the keys to the transcript and returns their weights in the aggregated key.
+pub(crate) fn key_weights(transcript: &mut Transcript, pubkeys: &[VerificationKey]) -> Vec<Scalar> {
+    transcript.commit_u64(b"n", pubkeys.len() as u64);
+    for p in pubkeys.iter() {
+        transcript.commit_point(b"P", &p.0);
+    }
+    pubkeys
+        .iter()
+        .map(|_| transcript.challenge_scalar(b"x"))
+        .collect()
+}
+
+/// Returns the weights of the keys in the combined key of `VerificationKey::aggregate`.
+pub(crate) fn aggregation_weights(pubkeys: &[VerificationKey]) -> Vec<Scalar> {
+    key_weights(&mut Transcript::new(b"ZkVM.aggregated-key"), pubkeys)
 }
 
 #[cfg(test)]
@@ -228,4 +365,156 @@ mod tests {
             .verify()
             .is_err());
     }
+
+    #[test]
+    fn deterministic_signature() {
+        let sign = || {
+            let mut transcript = Transcript::new(b"deterministic");
+            Signature::sign_single_deterministic(&mut transcript, Scalar::from(7u64)).to_bytes()
+        };
+        assert_eq!(sign()[..], sign()[..]);
+
+        let sig = Signature::from_bytes(sign()).unwrap();
+        let mut transcript = Transcript::new(b"deterministic");
+        let pubkey = VerificationKey::from_secret(&Scalar::from(7u64));
+        assert!(sig.verify_single(&mut transcript, pubkey).verify().is_ok());
+    }
+
+    #[test]
+    fn test_vectors() {
+        let transcript = || {
+            let mut t = Transcript::new(b"ZkVM.test-vector");
+            t.commit_bytes(b"message", b"hello");
+            t
+        };
+
+        let sig = Signature::sign_single_deterministic(&mut transcript(), Scalar::from(1u64));
+        assert_eq!(
+            hex::encode(&sig.to_bytes()[..]),
+            "ae2a8b4f46d459c19c72180d9b6446930f8dcb99340909b91f8b9fbb23ec7a03\
+             77c9d852fb1d431a4aac93d69fe33be6f776e305f2295a383120c8f2bd45cf06"
+        );
+
+        let sig = Signature::sign_aggregated_deterministic(
+            &mut transcript(),
+            &[Scalar::from(2u64), Scalar::from(3u64)],
+        );
+        assert_eq!(
+            hex::encode(&sig.to_bytes()[..]),
+            "ec11fbac8d693bcc99d90a52eb3615bafaca760c5e2d3b13669ee62aaf78d468\
+             77b81f5676dfb9b59d7577abc68842505fe4842483318c88cae5b0f44d80b702"
+        );
+    }
+
+    #[test]
+    fn aggregated_key() {
+        let privkeys = [
+            Scalar::random(&mut rand::thread_rng()),
+            Scalar::random(&mut rand::thread_rng()),
+        ];
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+        let combined = VerificationKey::aggregate(&pubkeys).unwrap();
+        let secret = VerificationKey::aggregate_secret(&privkeys);
+        assert_eq!(combined, VerificationKey::from_secret(&secret));
+
+        let mut transcript = Transcript::new(b"aggregated_key");
+        let sig = Signature::sign_single(&mut transcript, secret);
+        let mut transcript = Transcript::new(b"aggregated_key");
+        assert!(sig
+            .verify_single(&mut transcript, combined)
+            .verify()
+            .is_ok());
+
+        let invalid = VerificationKey(CompressedRistretto([0xff; 32]));
+        assert_eq!(
+            VerificationKey::aggregate(&[pubkeys[0], invalid]),
+            Err(VMError::InvalidPoint)
+        );
+    }
+
+    #[test]
+    fn key_encoding() {
+        let key = VerificationKey::from_secret(&Scalar::one());
+        assert_eq!(
+            key.to_hex(),
+            "e2f2ae0a6abc4e71a884a961c500515f58e30b6aa582dd8db6a65945e08d2d76"
+        );
+        assert_eq!(VerificationKey::from_hex(&key.to_hex()), Ok(key));
+        assert_eq!(VerificationKey::from_bytes(&key.to_bytes()), Ok(key));
+        assert_eq!(
+            VerificationKey::from_bytes(&[0u8; 31]),
+            Err(VMError::FormatError)
+        );
+        assert_eq!(
+            VerificationKey::from_bytes(&[0xff; 32]),
+            Err(VMError::InvalidPoint)
+        );
+        assert_eq!(VerificationKey::from_hex("zz"), Err(VMError::FormatError));
+    }
+
+    #[test]
+    fn batch_verification() {
+        let mut batch = (0..4)
+            .map(|i| {
+                let privkeys = (0..i + 1)
+                    .map(|_| Scalar::random(&mut rand::thread_rng()))
+                    .collect::<Vec<_>>();
+                let pubkeys = privkeys
+                    .iter()
+                    .map(VerificationKey::from_secret)
+                    .collect::<Vec<_>>();
+                let mut transcript = Transcript::new(b"batch");
+                transcript.commit_u64(b"i", i);
+                let sig = Signature::sign_aggregated(&mut transcript.clone(), &privkeys);
+                assert!(sig.verify(&mut transcript.clone(), &pubkeys).is_ok());
+                (transcript, pubkeys, sig)
+            })
+            .collect::<Vec<_>>();
+        assert!(Signature::verify_batch(&batch).is_ok());
+        assert!(Signature::verify_batch(&[]).is_ok());
+
+        batch[2].1.reverse();
+        assert!(Signature::verify_batch(&batch).is_err());
+        assert!(batch[2]
+            .2
+            .verify(&mut batch[2].0.clone(), &batch[2].1)
+            .is_err());
+    }
+
+    #[test]
+    fn combined_key_is_not_message_aggregation() {
+        let privkeys = (1..4u64).map(Scalar::from).collect::<Vec<_>>();
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+        let combined = VerificationKey::aggregate(&pubkeys).unwrap();
+        let transcript = || Transcript::new(b"message");
+
+        let aggregated = Signature::sign_aggregated(&mut transcript(), &privkeys);
+        assert!(aggregated
+            .verify_aggregated(&mut transcript(), &pubkeys)
+            .verify()
+            .is_ok());
+        assert!(aggregated
+            .verify_single(&mut transcript(), combined)
+            .verify()
+            .is_err());
+
+        let single = Signature::sign_single(
+            &mut transcript(),
+            VerificationKey::aggregate_secret(&privkeys),
+        );
+        assert!(single
+            .verify_single(&mut transcript(), combined)
+            .verify()
+            .is_ok());
+        assert!(single
+            .verify_aggregated(&mut transcript(), &pubkeys)
+            .verify()
+            .is_err());
+    }
 }
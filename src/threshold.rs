@@ -0,0 +1,490 @@
+//! Threshold (M-of-N) keys created with a dealerless Feldman VSS scheme.
+//!
+//! Each of the N participants deals a random polynomial of degree M-1 to the others,
+//! publishing commitments to its coefficients so that the dealt shares can be verified.
+//! The group key is the sum of the constant-term commitments, and each participant's
+//! secret share is the sum of the shares dealt to it.
+//! Each commitment carries a Schnorr proof of knowledge of the constant term,
+//! bound to the session transcript and the participant's index, so that a participant
+//! who publishes last cannot choose its commitment to cancel the others' contributions.
+//! Any M participants can then sign for the group key with the rounds of the `musig` protocol,
+//! producing a `Signature` that verifies with `Signature::verify_single`.
+//! Participants are identified by their index from 0 to N-1.
+#![allow(non_snake_case)]
+
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use std::fmt;
+
+use crate::errors::ThresholdError;
+use crate::gens;
+use crate::musig::{NoncePrecommitment, Party, PartyAwaitingPrecommitments};
+use crate::signature::{self, Signature, VerificationKey};
+use crate::transcript::TranscriptProtocol;
+
+/// Participant of the key generation.
+pub struct KeygenParticipant {
+    transcript: Transcript,
+    index: usize,
+    participants: usize,
+    coefficients: Vec<Scalar>,
+}
+
+/// Commitments to the coefficients of a participant's polynomial, published to all participants,
+/// with the proof of knowledge of the constant term.
+#[derive(Clone, Debug)]
+pub struct KeygenCommitment {
+    points: Vec<CompressedRistretto>,
+    proof: Signature,
+}
+
+/// Secret share dealt by one participant to another.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretShare(Scalar);
+
+/// Participant's share of the threshold key.
+#[derive(Clone)]
+pub struct ThresholdKey {
+    index: usize,
+    threshold: usize,
+    secret: Scalar,
+    group_key: VerificationKey,
+    /// Public counterparts of the secret shares of all participants.
+    verification_shares: Vec<RistrettoPoint>,
+}
+
+impl KeygenParticipant {
+    /// Starts the key generation for participant `index` out of `participants`,
+    /// creating a key that requires `threshold` participants to sign.
+    /// The transcript identifies the key generation session and must be the same for all participants.
+    /// Returns the participant state and the commitment to publish to the other participants.
+    pub fn new(
+        transcript: Transcript,
+        index: usize,
+        threshold: usize,
+        participants: usize,
+    ) -> Result<(Self, KeygenCommitment), ThresholdError> {
+        if threshold == 0 || threshold > participants || index >= participants {
+            return Err(ThresholdError::InvalidParameters);
+        }
+        let coefficients = (0..threshold)
+            .map(|_| Scalar::random(&mut rand::thread_rng()))
+            .collect::<Vec<_>>();
+        let B = gens::pedersen_gens().B;
+        let points = coefficients
+            .iter()
+            .map(|a| (a * B).compress())
+            .collect::<Vec<_>>();
+        let proof = Signature::sign_single(
+            &mut proof_transcript(&transcript, index, &points),
+            coefficients[0],
+        );
+        let commitment = KeygenCommitment { points, proof };
+        let participant = KeygenParticipant {
+            transcript,
+            index,
+            participants,
+            coefficients,
+        };
+        Ok((participant, commitment))
+    }
+
+    /// Creates the secret share for participant `recipient`.
+    /// The share must be sent over a private channel.
+    pub fn share_for(&self, recipient: usize) -> SecretShare {
+        let x = evaluation_point(recipient);
+        // Horner's method: a_0 + x*(a_1 + x*(a_2 + ...))
+        let value = self
+            .coefficients
+            .iter()
+            .rev()
+            .fold(Scalar::zero(), |acc, a| acc * x + a);
+        SecretShare(value)
+    }
+
+    /// Receives the commitments and the shares dealt by all participants, ordered by their index,
+    /// verifies the shares and returns the participant's share of the threshold key.
+    pub fn finish(
+        self,
+        commitments: Vec<KeygenCommitment>,
+        shares: Vec<SecretShare>,
+    ) -> Result<ThresholdKey, ThresholdError> {
+        check_count(self.participants, commitments.len())?;
+        check_count(self.participants, shares.len())?;
+        let threshold = self.coefficients.len();
+
+        let polynomials = commitments
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                if c.points.len() != threshold {
+                    return Err(ThresholdError::InvalidCommitment(i));
+                }
+                // a_0*B == C_0, proven with a Schnorr signature by a_0.
+                c.proof
+                    .verify_single(
+                        &mut proof_transcript(&self.transcript, i, &c.points),
+                        VerificationKey(c.points[0]),
+                    )
+                    .verify()
+                    .map_err(|_| ThresholdError::InvalidProof(i))?;
+                c.points
+                    .iter()
+                    .map(|p| p.decompress().ok_or(ThresholdError::InvalidCommitment(i)))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // s_i*B == Σ j^k*C_k
+        let B = gens::pedersen_gens().B;
+        for (i, (share, polynomial)) in shares.iter().zip(polynomials.iter()).enumerate() {
+            if share.0 * B != evaluate(polynomial, self.index) {
+                return Err(ThresholdError::InvalidShare(i));
+            }
+        }
+
+        let group_key: RistrettoPoint = polynomials.iter().map(|p| p[0]).sum();
+        let verification_shares = (0..self.participants)
+            .map(|j| polynomials.iter().map(|p| evaluate(p, j)).sum())
+            .collect();
+        Ok(ThresholdKey {
+            index: self.index,
+            threshold,
+            secret: shares.iter().map(|s| s.0).sum(),
+            group_key: VerificationKey(group_key.compress()),
+            verification_shares,
+        })
+    }
+}
+
+impl ThresholdKey {
+    /// Returns the index of the participant holding this share.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the number of participants required to sign.
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Returns the group key for which the participants sign.
+    pub fn group_key(&self) -> VerificationKey {
+        self.group_key
+    }
+
+    /// Starts the signing protocol among `signers`, a list of at least `threshold` distinct participant indices
+    /// including this participant's own index.
+    /// The transcript must be in the same state as the one passed to `Signature::verify_single`.
+    /// Messages of the following rounds are ordered as the `signers` list.
+    pub fn start_signing(
+        &self,
+        mut transcript: Transcript,
+        signers: &[usize],
+    ) -> Result<(PartyAwaitingPrecommitments, NoncePrecommitment), ThresholdError> {
+        let n = self.verification_shares.len();
+        if signers.len() < self.threshold
+            || signers.iter().any(|&j| j >= n)
+            || signers
+                .iter()
+                .enumerate()
+                .any(|(k, j)| signers[..k].contains(j))
+        {
+            return Err(ThresholdError::InvalidSignerSet);
+        }
+        let position = signers
+            .iter()
+            .position(|&j| j == self.index)
+            .ok_or(ThresholdError::InvalidSignerSet)?;
+
+        // Same weight as in `Signature::verify_single`, split among the signers with Lagrange coefficients.
+        let x = signature::key_weights(&mut transcript, &[self.group_key])[0];
+        let weights = signers
+            .iter()
+            .map(|&j| x * lagrange_coefficient(j, signers))
+            .collect();
+        let pubkeys = signers
+            .iter()
+            .map(|&j| self.verification_shares[j])
+            .collect();
+        Ok(Party::with_weights(
+            transcript,
+            self.secret,
+            position,
+            pubkeys,
+            weights,
+        ))
+    }
+}
+
+impl KeygenCommitment {
+    /// Encodes the commitment as a sequence of 32-byte points followed by the 64-byte proof.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self
+            .points
+            .iter()
+            .flat_map(|p| p.to_bytes().to_vec())
+            .collect::<Vec<_>>();
+        bytes.extend_from_slice(&self.proof.to_bytes()[..]);
+        bytes
+    }
+
+    /// Decodes the commitment from a sequence of 32-byte points followed by the 64-byte proof.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ThresholdError> {
+        if bytes.len() < 32 + 64 || bytes.len() % 32 != 0 {
+            return Err(ThresholdError::FormatError);
+        }
+        let (points, proof) = bytes.split_at(bytes.len() - 64);
+        let mut proof_bytes = [0u8; 64];
+        proof_bytes.copy_from_slice(proof);
+        Ok(KeygenCommitment {
+            points: points
+                .chunks(32)
+                .map(CompressedRistretto::from_slice)
+                .collect(),
+            proof: Signature::from_bytes(proof_bytes).map_err(|_| ThresholdError::FormatError)?,
+        })
+    }
+}
+
+impl fmt::Debug for SecretShare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretShare(<redacted>)")
+    }
+}
+
+impl fmt::Debug for ThresholdKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThresholdKey")
+            .field("index", &self.index)
+            .field("threshold", &self.threshold)
+            .field("secret", &"<redacted>")
+            .field("group_key", &self.group_key)
+            .field("verification_shares", &self.verification_shares)
+            .finish()
+    }
+}
+
+impl SecretShare {
+    /// Encodes the share as a 32-byte array.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    /// Decodes the share from a 32-byte array.
+    pub fn from_bytes(bytes: [u8; 32]) -> Result<Self, ThresholdError> {
+        Scalar::from_canonical_bytes(bytes)
+            .map(SecretShare)
+            .ok_or(ThresholdError::FormatError)
+    }
+}
+
+/// Prepares the transcript for the proof of knowledge of the constant term
+/// of the polynomial committed to by participant `index`.
+fn proof_transcript(
+    session: &Transcript,
+    index: usize,
+    points: &[CompressedRistretto],
+) -> Transcript {
+    let mut transcript = session.clone();
+    transcript.commit_bytes(b"dom-sep", b"ZkVM.keygen");
+    transcript.commit_u64(b"index", index as u64);
+    for point in points.iter() {
+        transcript.commit_point(b"C", point);
+    }
+    transcript
+}
+
+/// Polynomials are evaluated at `index+1`, since the value at zero is the secret.
+fn evaluation_point(index: usize) -> Scalar {
+    Scalar::from(index as u64 + 1)
+}
+
+/// Evaluates the committed polynomial at the point of participant `index`.
+fn evaluate(commitments: &[RistrettoPoint], index: usize) -> RistrettoPoint {
+    let x = evaluation_point(index);
+    commitments
+        .iter()
+        .rev()
+        .fold(RistrettoPoint::default(), |acc, c| acc * x + c)
+}
+
+/// Computes the coefficient of participant `index` for interpolating the secret from the shares of `signers`.
+fn lagrange_coefficient(index: usize, signers: &[usize]) -> Scalar {
+    let xi = evaluation_point(index);
+    let (num, den) = signers
+        .iter()
+        .filter(|&&j| j != index)
+        .map(|&j| evaluation_point(j))
+        .fold((Scalar::one(), Scalar::one()), |(num, den), xj| {
+            (num * xj, den * (xj - xi))
+        });
+    num * den.invert()
+}
+
+fn check_count(expected: usize, actual: usize) -> Result<(), ThresholdError> {
+    if actual != expected {
+        return Err(ThresholdError::WrongNumberOfMessages { expected, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::musig::{NonceCommitment, Share};
+    use crate::signature::Signature;
+
+    fn transcript() -> Transcript {
+        let mut t = Transcript::new(b"ZkVM.signtx");
+        t.commit_bytes(b"txid", &[9u8; 32]);
+        t
+    }
+
+    fn session() -> Transcript {
+        Transcript::new(b"ZkVM.keygen-test")
+    }
+
+    /// Runs the key generation among `n` participants, passing serialized messages.
+    fn keygen(threshold: usize, n: usize) -> Vec<ThresholdKey> {
+        let (participants, commitments): (Vec<_>, Vec<_>) = (0..n)
+            .map(|i| KeygenParticipant::new(session(), i, threshold, n).unwrap())
+            .unzip();
+        let commitments = commitments.iter().map(|c| c.to_bytes()).collect::<Vec<_>>();
+        let shares = participants
+            .iter()
+            .map(|dealer| (0..n).map(|j| dealer.share_for(j).to_bytes()).collect())
+            .collect::<Vec<Vec<_>>>();
+
+        participants
+            .into_iter()
+            .enumerate()
+            .map(|(j, p)| {
+                let commitments = commitments
+                    .iter()
+                    .map(|c| KeygenCommitment::from_bytes(c).unwrap())
+                    .collect();
+                let shares = shares
+                    .iter()
+                    .map(|dealt| SecretShare::from_bytes(dealt[j]).unwrap())
+                    .collect();
+                p.finish(commitments, shares).unwrap()
+            })
+            .collect()
+    }
+
+    fn sign(keys: &[ThresholdKey], signers: &[usize]) -> Signature {
+        let (parties, precommitments): (Vec<_>, Vec<_>) = signers
+            .iter()
+            .map(|&j| keys[j].start_signing(transcript(), signers).unwrap())
+            .unzip();
+        let (parties, commitments): (Vec<_>, Vec<_>) = parties
+            .into_iter()
+            .map(|p| p.receive_precommitments(precommitments.clone()).unwrap())
+            .unzip();
+        let commitments = commitments
+            .iter()
+            .map(|c| NonceCommitment::from_bytes(c.to_bytes()))
+            .collect::<Vec<_>>();
+        let (parties, shares): (Vec<_>, Vec<_>) = parties
+            .into_iter()
+            .map(|p| p.receive_commitments(commitments.clone()).unwrap())
+            .unzip();
+        let shares = shares
+            .iter()
+            .map(|s| Share::from_bytes(s.to_bytes()).unwrap())
+            .collect::<Vec<_>>();
+        let signatures = parties
+            .into_iter()
+            .map(|p| p.receive_shares(shares.clone()).unwrap())
+            .collect::<Vec<_>>();
+        signatures[0]
+    }
+
+    #[test]
+    fn threshold_signature() {
+        let keys = keygen(2, 3);
+        let group_key = keys[0].group_key();
+        assert!(keys.iter().all(|k| k.group_key() == group_key));
+
+        for signers in [vec![0, 1], vec![2, 0], vec![1, 2], vec![0, 1, 2]].iter() {
+            let sig = sign(&keys, signers);
+            let op = sig.verify_single(&mut transcript(), group_key);
+            assert!(op.verify().is_ok());
+        }
+    }
+
+    #[test]
+    fn invalid_signer_set() {
+        let keys = keygen(2, 3);
+        for signers in [vec![0], vec![0, 0], vec![0, 3], vec![1, 2]].iter() {
+            assert_eq!(
+                keys[0].start_signing(transcript(), signers).err(),
+                Some(ThresholdError::InvalidSignerSet)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_dealt_share() {
+        let (participants, commitments): (Vec<_>, Vec<_>) = (0..3)
+            .map(|i| KeygenParticipant::new(session(), i, 2, 3).unwrap())
+            .unzip();
+        let mut shares = participants
+            .iter()
+            .map(|dealer| dealer.share_for(0))
+            .collect::<Vec<_>>();
+        shares[2] = SecretShare(shares[2].0 + Scalar::one());
+        assert_eq!(format!("{:?}", shares[2]), "SecretShare(<redacted>)");
+        let receiver = participants.into_iter().next().unwrap();
+        assert_eq!(
+            receiver.finish(commitments, shares).err(),
+            Some(ThresholdError::InvalidShare(2))
+        );
+    }
+
+    #[test]
+    fn invalid_proof_of_knowledge() {
+        let mut commitments = (0..3)
+            .map(|i| KeygenParticipant::new(session(), i, 2, 3).unwrap().1)
+            .collect::<Vec<_>>();
+
+        // Participant 2 publishes last and cancels the constant terms of the others,
+        // reusing its own proof since it does not know the discrete log of the new point.
+        let others: RistrettoPoint = commitments[..2]
+            .iter()
+            .map(|c| c.points[0].decompress().unwrap())
+            .sum();
+        let mut rogue = commitments[2].clone();
+        rogue.points[0] = (rogue.points[0].decompress().unwrap() - others).compress();
+
+        // A valid proof made for another index or another session is rejected as well.
+        let other_index = KeygenCommitment {
+            points: commitments[2].points.clone(),
+            proof: commitments[1].proof,
+        };
+        let (_, other_session) =
+            KeygenParticipant::new(Transcript::new(b"other session"), 2, 2, 3).unwrap();
+
+        let shares = (0..3)
+            .map(|_| SecretShare(Scalar::zero()))
+            .collect::<Vec<_>>();
+        for commitment in vec![rogue, other_index, other_session] {
+            commitments[2] = commitment;
+            let (receiver, _) = KeygenParticipant::new(session(), 0, 2, 3).unwrap();
+            assert_eq!(
+                receiver.finish(commitments.clone(), shares.clone()).err(),
+                Some(ThresholdError::InvalidProof(2))
+            );
+        }
+    }
+
+    #[test]
+    fn redacted_debug() {
+        let keys = keygen(2, 3);
+        let debug = format!("{:?}", keys[0]);
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains(&format!("{:?}", keys[0].secret)));
+    }
+}
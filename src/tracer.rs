@@ -0,0 +1,75 @@
+//! Execution tracing for debugging ZkVM programs.
+//!
+//! A `Tracer` passed to `VM::verify_tx_traced` is notified after every executed instruction
+//! with a summary of the VM stack and the side effects of that instruction.
+use curve25519_dalek::ristretto::CompressedRistretto;
+
+use crate::errors::VMError;
+use crate::ops::Instruction;
+use crate::point_ops::PointOp;
+use crate::txlog::Entry;
+
+/// Receives a notification for each instruction executed by the VM.
+pub trait Tracer {
+    /// Called after the instruction is executed, or failed to execute.
+    fn step(&mut self, step: &TraceStep);
+}
+
+impl<F> Tracer for F
+where
+    F: FnMut(&TraceStep),
+{
+    fn step(&mut self, step: &TraceStep) {
+        self(step)
+    }
+}
+
+/// State of the VM after executing a single instruction.
+#[derive(Debug)]
+pub struct TraceStep<'a> {
+    /// Executed instruction.
+    pub instruction: Instruction,
+
+    /// Byte offset of the instruction within the current program.
+    pub offset: usize,
+
+    /// Number of suspended programs below the current one (0 for the transaction program).
+    pub depth: usize,
+
+    /// Summary of the stack items after the instruction, from the bottom to the top.
+    pub stack: Vec<ItemSummary>,
+
+    /// Deferred point operations added by the instruction.
+    pub point_ops: &'a [PointOp],
+
+    /// Txlog entries added by the instruction.
+    pub entries: &'a [Entry],
+
+    /// Error that aborted the execution at this instruction.
+    pub error: Option<&'a VMError>,
+}
+
+/// Summarized view of a stack item.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ItemSummary {
+    /// Data string of a given length.
+    Data(usize),
+
+    /// Contract with its predicate and the number of payload items.
+    Contract(CompressedRistretto, usize),
+
+    /// Value with its quantity and flavor commitments.
+    Value(CompressedRistretto, CompressedRistretto),
+
+    /// Wide value (its commitments are internal to the constraint system).
+    WideValue,
+
+    /// Variable with its commitment.
+    Variable(CompressedRistretto),
+
+    /// Expression with a given number of terms.
+    Expression(usize),
+
+    /// Constraint.
+    Constraint,
+}
@@ -19,11 +19,11 @@ pub enum Entry {
 }
 
 /// Transaction ID is a unique 32-byte identifier of a transaction
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct TxID(pub [u8; 32]);
 
 /// UTXO is a unique 32-byte identifier of a transaction output
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct UTXO(pub [u8; 32]);
 
 impl UTXO {
@@ -0,0 +1,440 @@
+//! Utreexo-style accumulator for the UTXO set.
+//!
+//! The accumulator is a forest of perfect merkle binary trees, at most one per height,
+//! mirroring the binary representation of the number of inserted items.
+//! Validators keep only the roots of the trees (O(log n) state), while the owners
+//! of the UTXOs keep the inclusion proofs and update them with every change of the forest.
+//!
+//! Deleted items are replaced with an empty leaf, so the positions of the remaining
+//! items never change and a deletion affects exactly one neighbor in any other proof.
+use merlin::Transcript;
+
+use crate::errors::VMError;
+use crate::txlog::{Entry, UTXO};
+use crate::vm::VerifiedTx;
+
+/// Accumulator for the UTXO identifiers.
+#[derive(Clone, Debug, Default)]
+pub struct Forest {
+    /// Number of items ever inserted in the forest.
+    count: u64,
+
+    /// Roots of the trees indexed by their height.
+    /// Root at height `h` is present iff bit `h` of `count` is set.
+    roots: Vec<Option<[u8; 32]>>,
+}
+
+/// Proof of inclusion of an item in the forest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InclusionProof {
+    /// Position of the item in the order of insertion.
+    pub position: u64,
+
+    /// Sibling hashes from the leaf up to the root of the tree containing the item.
+    pub neighbors: Vec<[u8; 32]>,
+}
+
+/// Description of a change to the forest that allows updating the existing proofs.
+#[derive(Clone, Debug)]
+pub struct ForestUpdate {
+    kind: UpdateKind,
+}
+
+#[derive(Clone, Debug)]
+enum UpdateKind {
+    /// Item inserted at `position` merged the trees with roots `merged` (lowest first).
+    /// `carries[h]` is the hash of the tree at height `h` that included the new item.
+    Insert {
+        position: u64,
+        carries: Vec<[u8; 32]>,
+        merged: Vec<[u8; 32]>,
+    },
+
+    /// Item at `position` was replaced with an empty leaf in the tree
+    /// of size `2^height` starting at `start`.
+    /// `path[h]` is the new hash of the node at height `h` on the path to the root.
+    Delete {
+        position: u64,
+        start: u64,
+        height: usize,
+        path: Vec<[u8; 32]>,
+    },
+}
+
+impl Forest {
+    /// Creates an empty forest.
+    pub fn new() -> Self {
+        Forest::default()
+    }
+
+    /// Returns the number of items ever inserted in the forest.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the roots of the trees indexed by their height.
+    pub fn roots(&self) -> &[Option<[u8; 32]>] {
+        &self.roots
+    }
+
+    /// Inserts an item and returns an update for the existing proofs.
+    /// The proof for the new item is available via `ForestUpdate::inserted_proof`.
+    pub fn insert(&mut self, utxo: &UTXO) -> ForestUpdate {
+        let position = self.count;
+        let mut carry = hash_leaf(utxo);
+        let mut carries = Vec::new();
+        let mut merged = Vec::new();
+        let mut height = 0;
+        loop {
+            if height == self.roots.len() {
+                self.roots.push(None);
+            }
+            match self.roots[height].take() {
+                Some(root) => {
+                    carries.push(carry);
+                    merged.push(root);
+                    carry = hash_node(&root, &carry);
+                    height += 1;
+                }
+                None => {
+                    self.roots[height] = Some(carry);
+                    break;
+                }
+            }
+        }
+        self.count += 1;
+
+        ForestUpdate {
+            kind: UpdateKind::Insert {
+                position,
+                carries,
+                merged,
+            },
+        }
+    }
+
+    /// Verifies that the item is included in the forest.
+    pub fn verify(&self, utxo: &UTXO, proof: &InclusionProof) -> Result<(), VMError> {
+        self.locate_root(utxo, proof).map(|_| ())
+    }
+
+    /// Deletes an item using its inclusion proof and returns an update for the other proofs.
+    pub fn delete(&mut self, utxo: &UTXO, proof: &InclusionProof) -> Result<ForestUpdate, VMError> {
+        let (height, start) = self.locate_root(utxo, proof)?;
+        let offset = proof.position - start;
+
+        let mut path = Vec::with_capacity(height);
+        let mut node = hash_empty();
+        for (level, neighbor) in proof.neighbors.iter().enumerate() {
+            path.push(node);
+            node = hash_parent(&node, neighbor, offset >> level);
+        }
+        self.roots[height] = Some(node);
+
+        Ok(ForestUpdate {
+            kind: UpdateKind::Delete {
+                position: proof.position,
+                start,
+                height,
+                path,
+            },
+        })
+    }
+
+    /// Applies the inputs and outputs of a verified transaction to the forest.
+    /// Proofs must be provided for all `Entry::Input` entries in the order of the log.
+    /// Returns the updates in the order of application; the forest is left intact on error.
+    pub fn apply_tx(
+        &mut self,
+        tx: &VerifiedTx,
+        proofs: &[InclusionProof],
+    ) -> Result<Vec<ForestUpdate>, VMError> {
+        let mut forest = self.clone();
+        let mut proofs = proofs.to_vec();
+        let mut updates = Vec::new();
+        let mut next_proof = 0;
+
+        for entry in tx.log.iter() {
+            let update = match entry {
+                Entry::Input(utxo) => {
+                    let proof = proofs
+                        .get(next_proof)
+                        .ok_or(VMError::InvalidMerkleProof)?
+                        .clone();
+                    next_proof += 1;
+                    forest.delete(utxo, &proof)?
+                }
+                Entry::Output(output) => forest.insert(&UTXO::from_output(output, &tx.id)),
+                _ => continue,
+            };
+            // Keep the proofs for the remaining inputs up to date.
+            for proof in proofs[next_proof..].iter_mut() {
+                update.update_proof(proof)?;
+            }
+            updates.push(update);
+        }
+
+        if next_proof != proofs.len() {
+            return Err(VMError::InvalidMerkleProof);
+        }
+
+        *self = forest;
+        Ok(updates)
+    }
+
+    /// Returns the height and the starting position of the tree containing the item.
+    fn locate_root(&self, utxo: &UTXO, proof: &InclusionProof) -> Result<(usize, u64), VMError> {
+        let (height, start) = self
+            .locate(proof.position)
+            .ok_or(VMError::InvalidMerkleProof)?;
+        if proof.neighbors.len() != height {
+            return Err(VMError::InvalidMerkleProof);
+        }
+
+        let offset = proof.position - start;
+        let mut node = hash_leaf(utxo);
+        for (level, neighbor) in proof.neighbors.iter().enumerate() {
+            node = hash_parent(&node, neighbor, offset >> level);
+        }
+
+        if self.roots[height] != Some(node) {
+            return Err(VMError::InvalidMerkleProof);
+        }
+        Ok((height, start))
+    }
+
+    /// Finds the tree containing a given position.
+    /// Trees are ordered by position from the highest to the lowest.
+    fn locate(&self, position: u64) -> Option<(usize, u64)> {
+        let mut start = 0u64;
+        for height in (0..self.roots.len()).rev() {
+            if self.roots[height].is_some() {
+                let size = 1u64 << height;
+                if position < start + size {
+                    return Some((height, start));
+                }
+                start += size;
+            }
+        }
+        None
+    }
+}
+
+impl ForestUpdate {
+    /// Returns the proof for the inserted item, if this is an insertion.
+    pub fn inserted_proof(&self) -> Option<InclusionProof> {
+        match &self.kind {
+            UpdateKind::Insert {
+                position, merged, ..
+            } => Some(InclusionProof {
+                position: *position,
+                neighbors: merged.clone(),
+            }),
+            UpdateKind::Delete { .. } => None,
+        }
+    }
+
+    /// Updates a proof made before this change.
+    /// Fails if the proof belongs to the deleted item.
+    pub fn update_proof(&self, proof: &mut InclusionProof) -> Result<(), VMError> {
+        match &self.kind {
+            UpdateKind::Insert {
+                position,
+                carries,
+                merged,
+            } => {
+                if proof.position >= *position {
+                    return Ok(());
+                }
+                // The tree of the proof is merged if it is one of the trees below the new one.
+                let height = proof.neighbors.len();
+                if height < merged.len() {
+                    proof.neighbors.push(carries[height]);
+                    proof.neighbors.extend_from_slice(&merged[height + 1..]);
+                }
+                Ok(())
+            }
+            UpdateKind::Delete {
+                position,
+                start,
+                height,
+                path,
+            } => {
+                if proof.position == *position {
+                    return Err(VMError::InvalidMerkleProof);
+                }
+                if proof.position < *start || proof.position - *start >= (1u64 << *height) {
+                    return Ok(());
+                }
+                // Paths to the root share all nodes above the highest differing bit of the offsets,
+                // so only the neighbor at that level has changed.
+                let diff = (proof.position - *start) ^ (*position - *start);
+                let level = 63 - diff.leading_zeros() as usize;
+                match proof.neighbors.get_mut(level) {
+                    Some(neighbor) => {
+                        *neighbor = path[level];
+                        Ok(())
+                    }
+                    None => Err(VMError::InvalidMerkleProof),
+                }
+            }
+        }
+    }
+}
+
+fn hash_leaf(utxo: &UTXO) -> [u8; 32] {
+    let mut t = Transcript::new(b"ZkVM.utreexo");
+    t.commit_bytes(b"utxo", &utxo.0);
+    let mut result = [0u8; 32];
+    t.challenge_bytes(b"merkle.leaf", &mut result);
+    result
+}
+
+fn hash_empty() -> [u8; 32] {
+    let mut t = Transcript::new(b"ZkVM.utreexo");
+    let mut result = [0u8; 32];
+    t.challenge_bytes(b"merkle.empty", &mut result);
+    result
+}
+
+fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut t = Transcript::new(b"ZkVM.utreexo");
+    t.commit_bytes(b"L", left);
+    t.commit_bytes(b"R", right);
+    let mut result = [0u8; 32];
+    t.challenge_bytes(b"merkle.node", &mut result);
+    result
+}
+
+/// Hashes the node with its neighbor; lowest bit of `offset` indicates the right-hand node.
+fn hash_parent(node: &[u8; 32], neighbor: &[u8; 32], offset: u64) -> [u8; 32] {
+    if offset & 1 == 0 {
+        hash_node(node, neighbor)
+    } else {
+        hash_node(neighbor, node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::CostReport;
+    use crate::txlog::TxID;
+
+    fn utxo(i: u8) -> UTXO {
+        UTXO([i; 32])
+    }
+
+    /// Inserts `n` items and returns the forest with up-to-date proofs.
+    fn make_forest(n: u8) -> (Forest, Vec<InclusionProof>) {
+        let mut forest = Forest::new();
+        let mut proofs: Vec<InclusionProof> = Vec::new();
+        for i in 0..n {
+            let update = forest.insert(&utxo(i));
+            for p in proofs.iter_mut() {
+                update.update_proof(p).unwrap();
+            }
+            proofs.push(update.inserted_proof().unwrap());
+        }
+        (forest, proofs)
+    }
+
+    #[test]
+    fn insert_and_verify() {
+        let (forest, proofs) = make_forest(11);
+        assert_eq!(forest.count(), 11);
+        // 11 = 0b1011
+        assert_eq!(
+            forest
+                .roots()
+                .iter()
+                .map(|r| r.is_some())
+                .collect::<Vec<_>>(),
+            vec![true, true, false, true]
+        );
+        for (i, p) in proofs.iter().enumerate() {
+            assert!(forest.verify(&utxo(i as u8), p).is_ok());
+        }
+        assert!(forest.verify(&utxo(1), &proofs[0]).is_err());
+    }
+
+    #[test]
+    fn delete_and_update() {
+        let (mut forest, mut proofs) = make_forest(7);
+
+        let update = forest.delete(&utxo(2), &proofs[2]).unwrap();
+        assert!(update.update_proof(&mut proofs[2]).is_err());
+        for (i, p) in proofs.iter_mut().enumerate() {
+            if i != 2 {
+                update.update_proof(p).unwrap();
+                assert!(forest.verify(&utxo(i as u8), p).is_ok());
+            }
+        }
+
+        // Deleted item cannot be deleted again.
+        assert!(forest.verify(&utxo(2), &proofs[2]).is_err());
+        assert!(forest.delete(&utxo(2), &proofs[2]).is_err());
+
+        // Inserting after deletion merges the trees and keeps proofs valid.
+        let update = forest.insert(&utxo(7));
+        for (i, p) in proofs.iter_mut().enumerate() {
+            if i != 2 {
+                update.update_proof(p).unwrap();
+                assert!(forest.verify(&utxo(i as u8), p).is_ok());
+            }
+        }
+        let proof7 = update.inserted_proof().unwrap();
+        assert!(forest.verify(&utxo(7), &proof7).is_ok());
+        assert!(forest.delete(&utxo(7), &proof7).is_ok());
+    }
+
+    #[test]
+    fn stale_proof() {
+        let (mut forest, proofs) = make_forest(4);
+        let update = forest.delete(&utxo(0), &proofs[0]).unwrap();
+        // Proof for the item 1 is not updated
+        assert!(forest.verify(&utxo(1), &proofs[1]).is_err());
+        let mut proof = proofs[1].clone();
+        update.update_proof(&mut proof).unwrap();
+        assert!(forest.verify(&utxo(1), &proof).is_ok());
+    }
+
+    #[test]
+    fn apply_tx() {
+        let (mut forest, proofs) = make_forest(5);
+        let tx = VerifiedTx {
+            version: 1,
+            mintime: 0,
+            maxtime: 0,
+            id: TxID([0xff; 32]),
+            log: vec![
+                Entry::Header(1, 0, 0),
+                Entry::Input(utxo(1)),
+                Entry::Output(vec![1, 2, 3]),
+                Entry::Input(utxo(3)),
+            ],
+            cost: CostReport::default(),
+        };
+
+        // Missing proof leaves the forest intact.
+        assert!(forest.apply_tx(&tx, &proofs[1..2]).is_err());
+        assert_eq!(forest.count(), 5);
+
+        let updates = forest
+            .apply_tx(&tx, &[proofs[1].clone(), proofs[3].clone()])
+            .unwrap();
+        assert_eq!(updates.len(), 3);
+        assert_eq!(forest.count(), 6);
+
+        let mut proof4 = proofs[4].clone();
+        for u in updates.iter() {
+            u.update_proof(&mut proof4).unwrap();
+        }
+        assert!(forest.verify(&utxo(4), &proof4).is_ok());
+
+        let mut output_proof = updates[1].inserted_proof().unwrap();
+        updates[2].update_proof(&mut output_proof).unwrap();
+        let output_utxo = UTXO::from_output(&[1, 2, 3], &TxID([0xff; 32]));
+        assert!(forest.verify(&output_utxo, &output_proof).is_ok());
+    }
+}
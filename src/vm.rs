@@ -1,18 +1,21 @@
 use bulletproofs::r1cs;
 use bulletproofs::r1cs::R1CSProof;
-use bulletproofs::{BulletproofGens, PedersenGens};
 use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
+use rayon::prelude::*;
 use spacesuit;
 use std::iter::FromIterator;
 
 use crate::encoding;
-use crate::errors::VMError;
+use crate::errors::{BatchError, ExecutionError, VMError};
+use crate::gens;
+use crate::limits::{CostReport, VMLimits};
 use crate::ops::Instruction;
-use crate::point_ops::PointOp;
+use crate::point_ops::{PointOp, PointOpOrigin};
 use crate::predicate::Predicate;
 use crate::signature::*;
+use crate::tracer::{ItemSummary, TraceStep, Tracer};
 use crate::transcript::TranscriptProtocol;
 use crate::txlog::{Entry, TxID, UTXO};
 use crate::types::*;
@@ -47,6 +50,84 @@ pub struct Tx {
     pub proof: R1CSProof,
 }
 
+impl Tx {
+    /// Encodes the transaction as
+    /// `LE64(version) || LE64(mintime) || LE64(maxtime) || LE32(len) || program || signature || proof`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(8 * 3 + 4 + self.program.len() + 64);
+        encoding::write_u64(self.version, &mut buf);
+        encoding::write_u64(self.mintime, &mut buf);
+        encoding::write_u64(self.maxtime, &mut buf);
+        encoding::write_u32(self.program.len() as u32, &mut buf);
+        encoding::write_bytes(&self.program, &mut buf);
+        encoding::write_bytes(&self.signature.to_bytes(), &mut buf);
+        encoding::write_bytes(&self.proof.to_bytes(), &mut buf);
+        buf
+    }
+
+    /// Decodes the transaction encoded with `to_bytes`.
+    pub fn from_bytes(slice: &[u8]) -> Result<Tx, VMError> {
+        let (version, rest) = encoding::read_u64(slice)?;
+        let (mintime, rest) = encoding::read_u64(rest)?;
+        let (maxtime, rest) = encoding::read_u64(rest)?;
+        let (program_len, rest) = encoding::read_usize(rest)?;
+        let (program, rest) = encoding::read_bytes(program_len, rest)?;
+        let (signature, rest) = encoding::read_bytes(64, rest)?;
+        let mut sig_buf = [0u8; 64];
+        sig_buf.copy_from_slice(signature);
+        let proof = R1CSProof::from_bytes(rest).map_err(|_| VMError::FormatError)?;
+        Ok(Tx {
+            version,
+            mintime,
+            maxtime,
+            program: program.to_vec(),
+            signature: Signature::from_bytes(sig_buf)?,
+            proof,
+        })
+    }
+}
+
+/// Transaction that is not yet signed and proven.
+pub struct UnsignedTx {
+    /// Version of the transaction
+    pub version: u64,
+
+    /// Timestamp before which tx is invalid (sec)
+    pub mintime: u64,
+
+    /// Timestamp after which tx is invalid (sec)
+    pub maxtime: u64,
+
+    /// Program representing the transaction
+    pub program: Vec<u8>,
+}
+
+/// Result of executing an unsigned transaction with `VM::dry_run`.
+pub struct DryRun {
+    /// Transaction ID to be signed
+    pub id: TxID,
+
+    /// List of inputs, outputs and nonces to be inserted/deleted in the blockchain state.
+    pub log: Vec<Entry>,
+
+    /// Keys that must sign the txid, in the order of aggregation.
+    pub signtx_keys: Vec<VerificationKey>,
+
+    /// Number of variables committed to the constraint system.
+    pub commitments: usize,
+
+    /// Resources used by the transaction, including its signature.
+    pub cost: CostReport,
+}
+
+impl DryRun {
+    /// Returns the capacity of `BulletproofGens` needed to prove the transaction:
+    /// the number of multipliers counted in `cost`, rounded up to a power of two.
+    pub fn gens_capacity(&self) -> usize {
+        self.cost.multipliers.next_power_of_two()
+    }
+}
+
 /// Represents a verified transaction: a txid and a list of state updates.
 pub struct VerifiedTx {
     /// Version of the transaction
@@ -63,8 +144,14 @@ pub struct VerifiedTx {
 
     // List of inputs, outputs and nonces to be inserted/deleted in the blockchain state.
     pub log: Vec<Entry>,
+
+    /// Resources used by the transaction.
+    pub cost: CostReport,
 }
 
+/// Executed transaction with its deferred point operations and their origins.
+type Executed = (VerifiedTx, Vec<PointOp>, Vec<PointOpOrigin>);
+
 /// The ZkVM state used to validate a transaction.
 pub struct VM<'tx, 'transcript, 'gens> {
     mintime: u64,
@@ -87,8 +174,18 @@ pub struct VM<'tx, 'transcript, 'gens> {
     txlog: Vec<Entry>,
     signtx_keys: Vec<VerificationKey>,
     deferred_operations: Vec<PointOp>,
+
+    // statements checked by the deferred operations, in the same order.
+    deferred_origins: Vec<PointOpOrigin>,
+
+    // offset of the currently executed instruction in the current run.
+    instruction_offset: usize,
+
     variable_commitments: Vec<VariableCommitment>,
     cs: r1cs::Verifier<'transcript, 'gens>,
+
+    limits: VMLimits,
+    cost: CostReport,
 }
 
 /// An state of running a single program string.
@@ -112,37 +209,123 @@ enum VariableCommitment {
 
 impl<'tx, 'transcript, 'gens> VM<'tx, 'transcript, 'gens> {
     /// Creates a new instance of ZkVM with the appropriate parameters
-    pub fn verify_tx(tx: &Tx, bp_gens: &BulletproofGens) -> Result<VerifiedTx, VMError> {
-        // Allow extension opcodes if tx version is above the currently supported one.
-        let extension = tx.version > CURRENT_VERSION;
+    pub fn verify_tx(tx: &Tx) -> Result<VerifiedTx, ExecutionError> {
+        Self::verify_tx_with_limits(tx, &VMLimits::default())
+    }
 
-        // Construct a CS verifier to be used during ZkVM execution.
-        let mut r1cs_transcript = Transcript::new(b"ZkVM.r1cs");
-        let pc_gens = PedersenGens::default();
-        let cs = r1cs::Verifier::new(&bp_gens, &pc_gens, &mut r1cs_transcript);
+    /// Verifies the transaction like `verify_tx`, enforcing the given resource limits.
+    pub fn verify_tx_with_limits(tx: &Tx, limits: &VMLimits) -> Result<VerifiedTx, ExecutionError> {
+        let (verified_tx, deferred_operations, origins) = Self::execute(tx, limits, None)?;
 
-        let mut vm = VM {
-            mintime: tx.mintime,
-            maxtime: tx.maxtime,
-            program: &tx.program,
+        // Verify all deferred crypto operations.
+        Self::verify_point_ops(&deferred_operations[..], &origins[..])?;
 
-            extension,
-            unique: false,
-            stack: Vec::new(),
+        Ok(verified_tx)
+    }
 
-            current_run: Run {
-                program: &tx.program,
-                offset: 0,
-            },
-            run_stack: Vec::new(),
-            txlog: vec![Entry::Header(tx.version, tx.mintime, tx.maxtime)],
-            signtx_keys: Vec::new(),
-            deferred_operations: Vec::new(),
-            variable_commitments: Vec::new(),
-            cs,
-        };
+    /// Verifies the transaction like `verify_tx`, reporting each executed instruction to the tracer.
+    pub fn verify_tx_traced(
+        tx: &Tx,
+        tracer: &mut dyn Tracer,
+    ) -> Result<VerifiedTx, ExecutionError> {
+        let (verified_tx, deferred_operations, origins) =
+            Self::execute(tx, &VMLimits::default(), Some(tracer))?;
+        Self::verify_point_ops(&deferred_operations[..], &origins[..])?;
+        Ok(verified_tx)
+    }
+
+    /// Verifies a batch of transactions, checking the deferred point operations
+    /// of all transactions (including their signatures) in a single multi-scalar multiplication.
+    /// R1CS proofs are verified one by one since bulletproofs does not support batching them.
+    /// If the batch fails, the error points to the first invalid transaction.
+    pub fn verify_txs(txs: &[Tx]) -> Result<Vec<VerifiedTx>, BatchError> {
+        let results = txs
+            .iter()
+            .map(|tx| Self::execute(tx, &VMLimits::default(), None));
+        Self::verify_executed_batch(results)
+    }
+
+    /// Verifies a batch of transactions like `verify_txs`, but executes
+    /// the transactions in parallel on the global thread pool
+    /// and then checks their deferred point operations at once.
+    pub fn verify_txs_parallel(txs: &[Tx]) -> Result<Vec<VerifiedTx>, BatchError> {
+        let results = txs
+            .par_iter()
+            .map(|tx| Self::execute(tx, &VMLimits::default(), None))
+            .collect::<Vec<_>>();
+        Self::verify_executed_batch(results.into_iter())
+    }
+
+    /// Merges the deferred point operations of executed transactions
+    /// and verifies them in one batch.
+    fn verify_executed_batch<I>(results: I) -> Result<Vec<VerifiedTx>, BatchError>
+    where
+        I: Iterator<Item = Result<Executed, ExecutionError>>,
+    {
+        let mut verified_txs = Vec::new();
+        let mut deferred_operations = Vec::new();
+        let mut origins = Vec::new();
+        let mut ranges = Vec::new();
+
+        for (index, result) in results.enumerate() {
+            let (verified_tx, ops, ops_origins) =
+                result.map_err(|error| BatchError { index, error })?;
+            let start = deferred_operations.len();
+            deferred_operations.extend(ops);
+            origins.extend(ops_origins.into_iter().map(|origin| match origin {
+                PointOpOrigin::Signtx(_) => PointOpOrigin::Signtx(index),
+                origin => origin,
+            }));
+            ranges.push(start..deferred_operations.len());
+            verified_txs.push(verified_tx);
+        }
+
+        // Verify deferred crypto operations of all transactions at once,
+        // falling back to per-transaction checks to find the offending one.
+        if PointOp::verify_batch(&deferred_operations[..]).is_err() {
+            for (index, range) in ranges.into_iter().enumerate() {
+                Self::verify_point_ops(&deferred_operations[range.clone()], &origins[range])
+                    .map_err(|error| BatchError { index, error })?;
+            }
+        }
+
+        Ok(verified_txs)
+    }
+
+    /// Verifies the deferred point operations in one batch,
+    /// falling back to verifying them one by one to locate the failing operation.
+    /// The fallback repeats a multiplication for every operation up to the failing one,
+    /// so an invalid transaction costs several times more to reject than a valid one to accept.
+    fn verify_point_ops(ops: &[PointOp], origins: &[PointOpOrigin]) -> Result<(), ExecutionError> {
+        PointOp::verify_batch(ops).map_err(|error| {
+            let point_op = PointOp::find_failure(ops);
+            ExecutionError {
+                point_op,
+                point_op_origin: point_op.map(|i| origins[i]),
+                ..ExecutionError::from(error)
+            }
+        })
+    }
+
+    /// Executes the transaction and verifies its R1CS proof.
+    /// Returns the verified transaction and the deferred point operations
+    /// (including the transaction signature) that remain to be checked, with their origins.
+    fn execute(
+        tx: &Tx,
+        limits: &VMLimits,
+        tracer: Option<&mut dyn Tracer>,
+    ) -> Result<Executed, ExecutionError> {
+        // The verifier borrows the generators for the whole execution, so they are sized
+        // for the largest constraint system that `check_limits` lets through.
+        let bp_gens = gens::bulletproof_gens(limits.max_multipliers);
+
+        // Construct a CS verifier to be used during ZkVM execution.
+        let mut r1cs_transcript = Transcript::new(b"ZkVM.r1cs");
+        let cs = r1cs::Verifier::new(&bp_gens, gens::pedersen_gens(), &mut r1cs_transcript);
+
+        let mut vm = VM::new(tx.version, tx.mintime, tx.maxtime, &tx.program, limits, cs)?;
 
-        vm.run()?;
+        vm.run(tracer)?;
 
         let txid = TxID::from_log(&vm.txlog[..]);
 
@@ -152,39 +335,129 @@ impl<'tx, 'transcript, 'gens> VM<'tx, 'transcript, 'gens> {
         let signtx_point_op = tx
             .signature
             .verify_aggregated(&mut signtx_transcript, &vm.signtx_keys[..]);
-        vm.deferred_operations.push(signtx_point_op);
-
-        // Verify all deferred crypto operations.
-        PointOp::verify_batch(&vm.deferred_operations[..])?;
+        vm.defer_point_op(signtx_point_op, PointOpOrigin::Signtx(0));
+        vm.check_limits()?;
 
         // Verify the R1CS proof
         vm.cs
             .verify(&tx.proof)
             .map_err(|_| VMError::InvalidR1CSProof)?;
 
-        Ok(VerifiedTx {
+        let verified_tx = VerifiedTx {
             version: tx.version,
             mintime: tx.mintime,
             maxtime: tx.maxtime,
             id: txid,
             log: vm.txlog,
+            cost: vm.cost,
+        };
+        Ok((verified_tx, vm.deferred_operations, vm.deferred_origins))
+    }
+
+    /// Executes the program without the signature and the proof.
+    /// Returns the shape of the constraint system, the txid to be signed
+    /// and the keys that must sign it.
+    pub fn dry_run(tx: &UnsignedTx) -> Result<DryRun, ExecutionError> {
+        // The generators are not used since the proof is not verified.
+        let bp_gens = gens::bulletproof_gens(0);
+        let mut r1cs_transcript = Transcript::new(b"ZkVM.r1cs");
+        let cs = r1cs::Verifier::new(&bp_gens, gens::pedersen_gens(), &mut r1cs_transcript);
+
+        let limits = VMLimits::default();
+        let mut vm = VM::new(tx.version, tx.mintime, tx.maxtime, &tx.program, &limits, cs)?;
+        vm.run(None)?;
+
+        vm.cost.add_signature(vm.signtx_keys.len());
+        vm.check_limits()?;
+        let commitments = vm
+            .variable_commitments
+            .iter()
+            .filter(|v| match v {
+                VariableCommitment::Attached(_, _) => true,
+                VariableCommitment::Detached(_) => false,
+            })
+            .count();
+
+        Ok(DryRun {
+            id: TxID::from_log(&vm.txlog[..]),
+            log: vm.txlog,
+            signtx_keys: vm.signtx_keys,
+            commitments,
+            cost: vm.cost,
+        })
+    }
+
+    fn new(
+        version: u64,
+        mintime: u64,
+        maxtime: u64,
+        program: &'tx [u8],
+        limits: &VMLimits,
+        cs: r1cs::Verifier<'transcript, 'gens>,
+    ) -> Result<Self, VMError> {
+        if program.len() > limits.max_program_size {
+            return Err(VMError::ProgramTooLarge);
+        }
+
+        // Allow extension opcodes if tx version is above the currently supported one.
+        let extension = version > CURRENT_VERSION;
+
+        Ok(VM {
+            mintime,
+            maxtime,
+            program,
+
+            extension,
+            unique: false,
+            stack: Vec::new(),
+
+            current_run: Run { program, offset: 0 },
+            run_stack: Vec::new(),
+            txlog: vec![Entry::Header(version, mintime, maxtime)],
+            signtx_keys: Vec::new(),
+            deferred_operations: Vec::new(),
+            deferred_origins: Vec::new(),
+            instruction_offset: 0,
+            variable_commitments: Vec::new(),
+            limits: *limits,
+            cost: CostReport::default(),
+            cs,
         })
     }
 
     /// Runs through the entire program and nested programs until completion.
-    fn run(&mut self) -> Result<(), VMError> {
+    fn run(&mut self, mut tracer: Option<&mut dyn Tracer>) -> Result<(), ExecutionError> {
         loop {
-            if !self.step()? {
-                break;
+            let program = self.current_run.program;
+            let offset = self.current_run.offset;
+            let depth = self.run_stack.len();
+
+            let result = match tracer {
+                Some(ref mut tracer) => self.traced_step(&mut **tracer),
+                None => self.step(),
+            };
+            match result {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(error) => {
+                    return Err(ExecutionError {
+                        error,
+                        instruction: Instruction::parse(&program[offset..]).map(|(i, _)| i),
+                        offset: Some(offset),
+                        depth: Some(depth),
+                        point_op: None,
+                        point_op_origin: None,
+                    });
+                }
             }
         }
 
         if self.stack.len() > 0 {
-            return Err(VMError::StackNotClean);
+            return Err(VMError::StackNotClean.into());
         }
 
         if self.unique == false {
-            return Err(VMError::NotUniqueTxid);
+            return Err(VMError::NotUniqueTxid.into());
         }
 
         Ok(())
@@ -204,6 +477,31 @@ impl<'tx, 'transcript, 'gens> VM<'tx, 'transcript, 'gens> {
         return false;
     }
 
+    /// Performs a step and reports the executed instruction to the tracer.
+    /// Steps that finish a program, or fail to parse an instruction, are not reported.
+    fn traced_step(&mut self, tracer: &mut dyn Tracer) -> Result<bool, VMError> {
+        let program = self.current_run.program;
+        let offset = self.current_run.offset;
+        let depth = self.run_stack.len();
+        let point_ops_count = self.deferred_operations.len();
+        let entries_count = self.txlog.len();
+
+        let result = self.step();
+
+        if let Some((instruction, _)) = Instruction::parse(&program[offset..]) {
+            tracer.step(&TraceStep {
+                instruction,
+                offset,
+                depth,
+                stack: self.summarize_stack(),
+                point_ops: &self.deferred_operations[point_ops_count..],
+                entries: &self.txlog[entries_count..],
+                error: result.as_ref().err(),
+            });
+        }
+        result
+    }
+
     /// Returns a flag indicating whether to continue the execution
     fn step(&mut self) -> Result<bool, VMError> {
         // Have we reached the end of the current program?
@@ -217,6 +515,7 @@ impl<'tx, 'transcript, 'gens> VM<'tx, 'transcript, 'gens> {
                 .ok_or(VMError::FormatError)?;
 
         // Immediately update the offset for the next instructions
+        self.instruction_offset = self.current_run.offset;
         self.current_run.offset += instr_size;
 
         match instr {
@@ -224,46 +523,65 @@ impl<'tx, 'transcript, 'gens> VM<'tx, 'transcript, 'gens> {
             Instruction::Drop => self.drop()?,
             Instruction::Dup(i) => self.dup(i)?,
             Instruction::Roll(i) => self.roll(i)?,
-            Instruction::Const => unimplemented!(),
-            Instruction::Var => unimplemented!(),
-            Instruction::Alloc => unimplemented!(),
-            Instruction::Mintime => unimplemented!(),
-            Instruction::Maxtime => unimplemented!(),
-            Instruction::Neg => unimplemented!(),
-            Instruction::Add => unimplemented!(),
-            Instruction::Mul => unimplemented!(),
-            Instruction::Eq => unimplemented!(),
-            Instruction::Range(_) => unimplemented!(),
-            Instruction::And => unimplemented!(),
-            Instruction::Or => unimplemented!(),
-            Instruction::Verify => unimplemented!(),
-            Instruction::Blind => unimplemented!(),
-            Instruction::Reblind => unimplemented!(),
-            Instruction::Unblind => unimplemented!(),
             Instruction::Issue => self.issue()?,
-            Instruction::Borrow => unimplemented!(),
             Instruction::Retire => self.retire()?,
-            Instruction::Qty => unimplemented!(),
-            Instruction::Flavor => unimplemented!(),
             Instruction::Cloak(m, n) => self.cloak(m, n)?,
-            Instruction::Import => unimplemented!(),
-            Instruction::Export => unimplemented!(),
             Instruction::Input => self.input()?,
             Instruction::Output(k) => self.output(k)?,
             Instruction::Contract(k) => self.contract(k)?,
             Instruction::Nonce => self.nonce()?,
-            Instruction::Log => unimplemented!(),
             Instruction::Signtx => self.signtx()?,
-            Instruction::Call => unimplemented!(),
-            Instruction::Left => unimplemented!(),
-            Instruction::Right => unimplemented!(),
-            Instruction::Delegate => unimplemented!(),
             Instruction::Ext(opcode) => self.ext(opcode)?,
+            // Instructions that are not implemented yet fail instead of aborting the process.
+            Instruction::Const
+            | Instruction::Var
+            | Instruction::Alloc
+            | Instruction::Mintime
+            | Instruction::Maxtime
+            | Instruction::Neg
+            | Instruction::Add
+            | Instruction::Mul
+            | Instruction::Eq
+            | Instruction::Range(_)
+            | Instruction::And
+            | Instruction::Or
+            | Instruction::Verify
+            | Instruction::Blind
+            | Instruction::Reblind
+            | Instruction::Unblind
+            | Instruction::Borrow
+            | Instruction::Qty
+            | Instruction::Flavor
+            | Instruction::Import
+            | Instruction::Export
+            | Instruction::Log
+            | Instruction::Call
+            | Instruction::Left
+            | Instruction::Right
+            | Instruction::Delegate => return Err(VMError::UnsupportedInstruction),
         }
 
+        self.check_limits()?;
+
         return Ok(true);
     }
 
+    fn check_limits(&self) -> Result<(), VMError> {
+        if self.stack.len() > self.limits.max_stack_items {
+            return Err(VMError::StackLimitExceeded);
+        }
+        if self.run_stack.len() > self.limits.max_run_depth {
+            return Err(VMError::RunDepthLimitExceeded);
+        }
+        if self.cost.multipliers > self.limits.max_multipliers {
+            return Err(VMError::MultiplierLimitExceeded);
+        }
+        if self.cost.msm_size > self.limits.max_deferred_points {
+            return Err(VMError::DeferredPointLimitExceeded);
+        }
+        Ok(())
+    }
+
     fn pushdata(&mut self, len: usize) -> Result<(), VMError> {
         let range = self.current_run.offset - len..self.current_run.offset;
         self.stack.push(Item::Data(Data {
@@ -331,11 +649,14 @@ impl<'tx, 'transcript, 'gens> VM<'tx, 'transcript, 'gens> {
 
         let flv_scalar = Value::issue_flavor(&predicate);
         // flv_point == flavor·B    ->   0 == -flv_point + flv_scalar·B
-        self.deferred_operations.push(PointOp {
-            primary: Some(flv_scalar),
-            secondary: None,
-            arbitrary: vec![(-Scalar::one(), flv_point)],
-        });
+        self.defer_point_op(
+            PointOp {
+                primary: Some(flv_scalar),
+                secondary: None,
+                arbitrary: vec![(-Scalar::one(), flv_point)],
+            },
+            PointOpOrigin::IssueFlavor(self.instruction_offset),
+        );
 
         let qty_expr = self.variable_to_expression(qty);
         self.add_range_proof(64, qty_expr)?;
@@ -407,14 +728,11 @@ impl<'tx, 'transcript, 'gens> VM<'tx, 'transcript, 'gens> {
         // _widevalues commitments_ **cloak:_m_:_n_** → _values_
         // Merges and splits `m` [wide values](#wide-value-type) into `n` [values](#values).
 
-        if m > self.stack.len() || n > self.stack.len() {
-            return Err(VMError::StackUnderflow);
-        }
-        // Now that individual m and n are bounded by the (not even close to overflow) stack size,
-        // we can add them together.
-        // This does not overflow if the stack size is below 2^30 items.
-        assert!(self.stack.len() < (1usize << 30));
-        if (m + 2 * n) > self.stack.len() {
+        let items_count = n
+            .checked_mul(2)
+            .and_then(|x| x.checked_add(m))
+            .ok_or(VMError::StackUnderflow)?;
+        if items_count > self.stack.len() {
             return Err(VMError::StackUnderflow);
         }
 
@@ -450,6 +768,9 @@ impl<'tx, 'transcript, 'gens> VM<'tx, 'transcript, 'gens> {
             cloak_ins.insert(0, cloak_value);
         }
 
+        // Check the size of the gadget before it allocates the multipliers.
+        self.cost.add_cloak(m, n);
+        self.check_limits()?;
         spacesuit::cloak(&mut self.cs, cloak_ins, cloak_outs).map_err(|_| VMError::FormatError)?;
 
         // Push in the same order.
@@ -495,6 +816,30 @@ impl<'tx, 'transcript, 'gens> VM<'tx, 'transcript, 'gens> {
         self.stack.push(item.into())
     }
 
+    fn defer_point_op(&mut self, op: PointOp, origin: PointOpOrigin) {
+        self.cost.add_point_op(&op);
+        self.deferred_operations.push(op);
+        self.deferred_origins.push(origin);
+    }
+
+    fn summarize_stack(&self) -> Vec<ItemSummary> {
+        self.stack
+            .iter()
+            .map(|item| match item {
+                Item::Data(d) => ItemSummary::Data(d.bytes.len()),
+                Item::Contract(c) => ItemSummary::Contract(c.predicate.0, c.payload.len()),
+                Item::Value(v) => ItemSummary::Value(
+                    self.get_variable_commitment(v.qty),
+                    self.get_variable_commitment(v.flv),
+                ),
+                Item::WideValue(_) => ItemSummary::WideValue,
+                Item::Variable(v) => ItemSummary::Variable(self.get_variable_commitment(*v)),
+                Item::Expression(e) => ItemSummary::Expression(e.terms.len()),
+                Item::Constraint(_) => ItemSummary::Constraint,
+            })
+            .collect()
+    }
+
     fn make_variable(&mut self, commitment: CompressedRistretto) -> Variable {
         let index = self.variable_commitments.len();
         self.variable_commitments
@@ -658,7 +1003,9 @@ impl<'tx, 'transcript, 'gens> VM<'tx, 'transcript, 'gens> {
             None,
             bitrange,
         )
-        .map_err(|_| VMError::R1CSInconsistency)
+        .map_err(|_| VMError::R1CSInconsistency)?;
+        self.cost.add_range_proof(bitrange);
+        Ok(())
     }
 }
 
@@ -674,3 +1021,518 @@ impl<'tx> Contract<'tx> {
         size
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::assembler::assemble_with;
+    use crate::musig::{self, Party};
+    use crate::ops::Opcode;
+    use bulletproofs::r1cs::R1CSError;
+    use bulletproofs::BulletproofGens;
+    use std::collections::HashMap;
+
+    /// Assembles the program, resolving `<name>` items with the provided data strings.
+    pub(crate) fn program(source: &str, bindings: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let bindings = bindings
+            .iter()
+            .map(|(name, data)| (name.to_string(), data.clone()))
+            .collect::<HashMap<_, _>>();
+        assemble_with(source, &bindings).unwrap()
+    }
+
+    /// Returns the predicate of the private key as a data string.
+    pub(crate) fn key(privkey: u64) -> Vec<u8> {
+        VerificationKey::from_secret(&Scalar::from(privkey))
+            .0
+            .to_bytes()
+            .to_vec()
+    }
+
+    /// Makes a transaction with the program, signed by the private keys in the order of `signtx`.
+    pub(crate) fn make_tx(program: Vec<u8>, mintime: u64, maxtime: u64, privkeys: &[u64]) -> Tx {
+        let privkeys = privkeys
+            .iter()
+            .map(|p| Scalar::from(*p))
+            .collect::<Vec<_>>();
+        make_tx_with(program, mintime, maxtime, |mut transcript| {
+            Signature::sign_aggregated(&mut transcript, &privkeys)
+        })
+    }
+
+    /// Makes a transaction with the program, signed by `sign` given the `signtx` transcript.
+    pub(crate) fn make_tx_with<F>(program: Vec<u8>, mintime: u64, maxtime: u64, sign: F) -> Tx
+    where
+        F: FnOnce(Transcript) -> Signature,
+    {
+        let unsigned = UnsignedTx {
+            version: 1,
+            mintime,
+            maxtime,
+            program,
+        };
+        let dry_run = VM::dry_run(&unsigned).unwrap();
+
+        let mut signtx_transcript = Transcript::new(b"ZkVM.signtx");
+        signtx_transcript.commit_bytes(b"txid", &dry_run.id.0);
+        let signature = sign(signtx_transcript);
+
+        let bp_gens = gens::bulletproof_gens(dry_run.gens_capacity());
+        let mut r1cs_transcript = Transcript::new(b"ZkVM.r1cs");
+        let proof = r1cs::Prover::new(&bp_gens, gens::pedersen_gens(), &mut r1cs_transcript)
+            .prove()
+            .unwrap();
+
+        Tx {
+            version: unsigned.version,
+            mintime,
+            maxtime,
+            program: unsigned.program,
+            signature,
+            proof,
+        }
+    }
+
+    /// Returns the `input` data string spending the output of the transaction at the given position in its log.
+    pub(crate) fn spend(tx: &Tx, output: usize) -> Vec<u8> {
+        let verified = VM::verify_tx(tx).unwrap();
+        let outputs = verified
+            .log
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Output(output) => Some(output),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut input = verified.id.0.to_vec();
+        input.extend_from_slice(outputs[output]);
+        input
+    }
+
+    fn nonce_tx(privkey: u64, signer: u64) -> Tx {
+        let prog = program("<k> nonce signtx", &[("k", key(privkey))]);
+        make_tx(prog, 0, 100, &[signer])
+    }
+
+    #[test]
+    fn batch_error_index() {
+        let txs = vec![nonce_tx(1, 1), nonce_tx(2, 2), nonce_tx(3, 3)];
+        assert_eq!(VM::verify_txs(&txs).unwrap().len(), 3);
+
+        // Signature of the second transaction is made with a wrong key.
+        let txs = vec![nonce_tx(1, 1), nonce_tx(2, 5), nonce_tx(3, 3)];
+        for result in vec![VM::verify_txs(&txs), VM::verify_txs_parallel(&txs)] {
+            match result {
+                Err(BatchError { index, error }) => {
+                    assert_eq!(index, 1);
+                    assert_eq!(error.error, VMError::PointOperationFailed);
+                }
+                Ok(_) => panic!("batch with an invalid signature must fail"),
+            }
+        }
+
+        // Program of the third transaction fails during execution.
+        let mut txs = txs;
+        txs[1] = nonce_tx(2, 2);
+        txs[2].program = vec![Opcode::Drop.to_u8()];
+        assert_eq!(VM::verify_txs(&txs).err().map(|e| e.index), Some(2));
+    }
+
+    #[test]
+    fn tx_encoding() {
+        let tx = nonce_tx(1, 1);
+        let bytes = tx.to_bytes();
+        let decoded = Tx::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.version, tx.version);
+        assert_eq!(decoded.mintime, tx.mintime);
+        assert_eq!(decoded.maxtime, tx.maxtime);
+        assert_eq!(decoded.program, tx.program);
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert!(VM::verify_tx(&decoded).is_ok());
+
+        // Cuts within the header, the program or the signature are detected.
+        let proof_offset = 8 * 3 + 4 + tx.program.len() + 64;
+        for len in 0..proof_offset {
+            assert_eq!(
+                Tx::from_bytes(&bytes[..len]).err(),
+                Some(VMError::FormatError)
+            );
+        }
+    }
+
+    #[test]
+    fn traced_steps() {
+        let prog = program(
+            "<k> nonce signtx 0x01 <k2> output:1",
+            &[("k", key(1)), ("k2", key(2))],
+        );
+        let tx = make_tx(prog, 0, 100, &[1]);
+        let predicate = Predicate(CompressedRistretto::from_slice(&key(1)));
+
+        let mut steps = Vec::new();
+        let verified = VM::verify_tx_traced(&tx, &mut |step: &TraceStep| {
+            assert_eq!(step.depth, 0);
+            assert!(step.point_ops.is_empty());
+            assert!(step.error.is_none());
+            steps.push((
+                step.instruction,
+                step.offset,
+                step.stack.clone(),
+                step.entries.to_vec(),
+            ));
+        })
+        .unwrap();
+
+        assert_eq!(
+            steps,
+            vec![
+                (
+                    Instruction::Push(32),
+                    0,
+                    vec![ItemSummary::Data(32)],
+                    vec![]
+                ),
+                (
+                    Instruction::Nonce,
+                    37,
+                    vec![ItemSummary::Contract(predicate.0, 0)],
+                    vec![Entry::Nonce(predicate, 100)],
+                ),
+                (Instruction::Signtx, 38, vec![], vec![]),
+                (Instruction::Push(1), 39, vec![ItemSummary::Data(1)], vec![]),
+                (
+                    Instruction::Push(32),
+                    45,
+                    vec![ItemSummary::Data(1), ItemSummary::Data(32)],
+                    vec![],
+                ),
+                (
+                    Instruction::Output(1),
+                    82,
+                    vec![],
+                    vec![verified.log[2].clone()],
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn traced_point_ops() {
+        let bp_gens = gens::bulletproof_gens(0);
+        let mut r1cs_transcript = Transcript::new(b"ZkVM.r1cs");
+        let cs = r1cs::Verifier::new(&bp_gens, gens::pedersen_gens(), &mut r1cs_transcript);
+        let program = [Opcode::Issue.to_u8()];
+        let mut vm = VM::new(1, 0, 100, &program, &VMLimits::default(), cs).unwrap();
+
+        // Stack for `issue`: qty flv predicate
+        let qty = key(3);
+        let flv = key(4);
+        let predicate = key(1);
+        let qty_var = vm.make_variable(CompressedRistretto::from_slice(&qty));
+        let flv_var = vm.make_variable(CompressedRistretto::from_slice(&flv));
+        vm.push_item(qty_var);
+        vm.push_item(flv_var);
+        vm.push_item(Data { bytes: &predicate });
+
+        let mut steps = 0;
+        let result = vm.run(Some(&mut |step: &TraceStep| {
+            steps += 1;
+            assert_eq!(step.instruction, Instruction::Issue);
+            assert_eq!(step.offset, 0);
+            assert_eq!(step.point_ops.len(), 1);
+            assert_eq!(step.point_ops[0].arbitrary.len(), 1);
+            assert_eq!(step.point_ops[0].arbitrary[0].1.as_bytes(), &flv[..]);
+            assert_eq!(
+                step.entries,
+                &[Entry::Issue(
+                    CompressedRistretto::from_slice(&qty),
+                    CompressedRistretto::from_slice(&flv)
+                )][..]
+            );
+            assert_eq!(
+                step.stack,
+                vec![ItemSummary::Contract(
+                    CompressedRistretto::from_slice(&predicate),
+                    1
+                )]
+            );
+        }));
+        assert_eq!(steps, 1);
+        assert_eq!(result.err().map(|e| e.error), Some(VMError::StackNotClean));
+    }
+
+    #[test]
+    fn execution_error_location() {
+        let mut tx = nonce_tx(1, 1);
+        tx.program = program("0x01 drop drop", &[]);
+        let error = VM::verify_tx(&tx).err().unwrap();
+        assert_eq!(error.error, VMError::StackUnderflow);
+        assert_eq!(error.instruction, Some(Instruction::Drop));
+        assert_eq!(error.offset, Some(7));
+        assert_eq!(error.depth, Some(0));
+        assert_eq!(error.point_op, None);
+
+        // Nested programs are not reachable until `call` is implemented,
+        // so the run is suspended by hand.
+        let bp_gens = gens::bulletproof_gens(0);
+        let mut r1cs_transcript = Transcript::new(b"ZkVM.r1cs");
+        let cs = r1cs::Verifier::new(&bp_gens, gens::pedersen_gens(), &mut r1cs_transcript);
+        let outer = program("0x01 drop", &[]);
+        let inner = program("0x01 0x02 roll:1 drop drop drop", &[]);
+        let mut vm = VM::new(1, 0, 100, &outer, &VMLimits::default(), cs).unwrap();
+        vm.run_stack.push(Run {
+            program: &outer,
+            offset: 6,
+        });
+        vm.current_run = Run {
+            program: &inner,
+            offset: 0,
+        };
+        let error = vm.run(None).err().unwrap();
+        assert_eq!(error.error, VMError::StackUnderflow);
+        assert_eq!(error.instruction, Some(Instruction::Drop));
+        assert_eq!(error.offset, Some(19));
+        assert_eq!(error.depth, Some(1));
+    }
+
+    #[test]
+    fn signtx_point_op_origin() {
+        let tx = nonce_tx(1, 2);
+        let error = VM::verify_tx(&tx).err().unwrap();
+        assert_eq!(error.error, VMError::PointOperationFailed);
+        assert_eq!(error.point_op, Some(0));
+        assert_eq!(error.point_op_origin, Some(PointOpOrigin::Signtx(0)));
+        assert_eq!(error.instruction, None);
+    }
+
+    #[test]
+    fn batch_point_op_origin() {
+        // Only the last transaction is signed with a wrong key.
+        let txs = vec![nonce_tx(1, 1), nonce_tx(2, 2), nonce_tx(3, 5)];
+        for result in vec![VM::verify_txs(&txs), VM::verify_txs_parallel(&txs)] {
+            let BatchError { index, error } = result.err().unwrap();
+            assert_eq!(index, 2);
+            assert_eq!(error.error, VMError::PointOperationFailed);
+            assert_eq!(error.point_op, Some(0));
+            assert_eq!(error.point_op_origin, Some(PointOpOrigin::Signtx(2)));
+            assert_eq!(
+                error.point_op_origin.unwrap().to_string(),
+                "signature of transaction 2"
+            );
+        }
+    }
+
+    #[test]
+    fn oversized_cloak() {
+        let bp_gens = gens::bulletproof_gens(0);
+        let mut r1cs_transcript = Transcript::new(b"ZkVM.r1cs");
+        let cs = r1cs::Verifier::new(&bp_gens, gens::pedersen_gens(), &mut r1cs_transcript);
+        let program = program("cloak:1:2", &[]);
+        let limits = VMLimits {
+            max_multipliers: 100,
+            ..VMLimits::default()
+        };
+        let mut vm = VM::new(1, 0, 100, &program, &limits, cs).unwrap();
+
+        // Stack for `cloak:1:2`: value qty1 flv1 qty2 flv2
+        let commitment = key(1);
+        let qty = vm.make_variable(CompressedRistretto::from_slice(&commitment));
+        let flv = vm.make_variable(CompressedRistretto::from_slice(&commitment));
+        vm.push_item(Value { qty, flv });
+        for _ in 0..4 {
+            vm.push_item(Data { bytes: &commitment });
+        }
+
+        let error = vm.run(None).err().unwrap();
+        assert_eq!(error.error, VMError::MultiplierLimitExceeded);
+        assert_eq!(error.instruction, Some(Instruction::Cloak(1, 2)));
+        assert!(vm.cost.multipliers > limits.max_multipliers);
+    }
+
+    /// Quantity and flavor of the value cloaked by `cloak_tx`.
+    const CLOAK_QTY: u64 = 10;
+    const CLOAK_FLV: u64 = 7;
+
+    /// Makes a transaction that spends a value and cloaks it into a new output.
+    /// The value comes from an input because `issue` needs variables
+    /// that only unsupported instructions produce.
+    fn cloak_tx() -> UnsignedTx {
+        let commit = |v: u64, blinding: u64| {
+            gens::pedersen_gens()
+                .commit(Scalar::from(v), Scalar::from(blinding))
+                .compress()
+                .to_bytes()
+                .to_vec()
+        };
+        // Spends the output of a made-up transaction: predicate, LE32(1), value.
+        let mut input = vec![0u8; 32];
+        input.extend_from_slice(&key(1));
+        input.extend_from_slice(&[1, 0, 0, 0, VALUE_TYPE]);
+        input.extend_from_slice(&commit(CLOAK_QTY, 1));
+        input.extend_from_slice(&commit(CLOAK_FLV, 2));
+
+        let prog = program(
+            "<input> input signtx <qty> <flv> cloak:1:1 <k> output:1",
+            &[
+                ("input", input),
+                ("qty", commit(CLOAK_QTY, 3)),
+                ("flv", commit(CLOAK_FLV, 4)),
+                ("k", key(2)),
+            ],
+        );
+        UnsignedTx {
+            version: 1,
+            mintime: 0,
+            maxtime: 100,
+            program: prog,
+        }
+    }
+
+    /// Proves the constraint system of `cloak_tx` with the generators.
+    fn prove_cloak(bp_gens: &BulletproofGens) -> Result<R1CSProof, R1CSError> {
+        let mut transcript = Transcript::new(b"ZkVM.r1cs");
+        let mut prover = r1cs::Prover::new(bp_gens, gens::pedersen_gens(), &mut transcript);
+        let assignment = spacesuit::Value {
+            q: CLOAK_QTY,
+            f: Scalar::from(CLOAK_FLV),
+        };
+        // The VM attaches the output value before the input value.
+        let mut values = [(3u64, 4u64), (1, 2)]
+            .iter()
+            .map(|(qty_blinding, flv_blinding)| {
+                let (_, q) = prover.commit(Scalar::from(CLOAK_QTY), Scalar::from(*qty_blinding));
+                let (_, f) = prover.commit(Scalar::from(CLOAK_FLV), Scalar::from(*flv_blinding));
+                spacesuit::AllocatedValue {
+                    q,
+                    f,
+                    assignment: Some(assignment),
+                }
+            })
+            .collect::<Vec<_>>();
+        let input = values.pop().unwrap();
+        let output = values.pop().unwrap();
+        spacesuit::cloak(&mut prover, vec![input], vec![output])?;
+        prover.prove()
+    }
+
+    #[test]
+    fn cloak_proof() {
+        let unsigned = cloak_tx();
+        let dry_run = VM::dry_run(&unsigned).unwrap();
+
+        // The cached generators may be larger than requested, so the exact capacity is made here:
+        // proving fails if the cost model underestimates the multipliers of the gadget.
+        let bp_gens = BulletproofGens::new(dry_run.gens_capacity(), 1);
+        let proof = prove_cloak(&bp_gens).unwrap();
+
+        let mut signtx_transcript = Transcript::new(b"ZkVM.signtx");
+        signtx_transcript.commit_bytes(b"txid", &dry_run.id.0);
+        let tx = Tx {
+            version: unsigned.version,
+            mintime: unsigned.mintime,
+            maxtime: unsigned.maxtime,
+            program: unsigned.program,
+            signature: Signature::sign_aggregated(&mut signtx_transcript, &[Scalar::one()]),
+            proof,
+        };
+        let verified = VM::verify_tx(&tx).unwrap();
+        assert_eq!(verified.id, dry_run.id);
+        assert_eq!(verified.cost, dry_run.cost);
+    }
+
+    #[test]
+    fn dry_run_cloak() {
+        let dry_run = VM::dry_run(&cloak_tx()).unwrap();
+        assert_eq!(
+            dry_run.signtx_keys,
+            vec![VerificationKey::from_secret(&Scalar::one())]
+        );
+        // Quantity and flavor of the input and of the output.
+        assert_eq!(dry_run.commitments, 4);
+        assert_eq!(dry_run.cost.range_proofs, 1);
+        assert!(dry_run.cost.multipliers > 64);
+        assert_eq!(
+            dry_run.gens_capacity(),
+            dry_run.cost.multipliers.next_power_of_two()
+        );
+
+        // The reported capacity is what the prover needs: half of it is not enough.
+        assert!(prove_cloak(&BulletproofGens::new(dry_run.gens_capacity(), 1)).is_ok());
+        assert_eq!(
+            prove_cloak(&BulletproofGens::new(dry_run.gens_capacity() / 2, 1)).err(),
+            Some(R1CSError::InvalidGeneratorsLength)
+        );
+    }
+
+    #[test]
+    fn dry_run() {
+        let prog = program(
+            "<k> nonce signtx 0x01 <k2> output:1",
+            &[("k", key(1)), ("k2", key(2))],
+        );
+        let unsigned = UnsignedTx {
+            version: 1,
+            mintime: 0,
+            maxtime: 100,
+            program: prog.clone(),
+        };
+        let dry_run = VM::dry_run(&unsigned).unwrap();
+        let verified = VM::verify_tx(&make_tx(prog, 0, 100, &[1])).unwrap();
+        assert_eq!(dry_run.id, verified.id);
+        assert_eq!(dry_run.log, verified.log);
+        assert_eq!(
+            dry_run.signtx_keys,
+            vec![VerificationKey::from_secret(&Scalar::from(1u64))]
+        );
+        assert_eq!(dry_run.commitments, 0);
+        assert_eq!(dry_run.cost, verified.cost);
+        assert_eq!(dry_run.cost.point_ops, 1);
+        assert_eq!(dry_run.cost.msm_size, 2 + 2);
+        assert_eq!(dry_run.gens_capacity(), 1);
+
+        let unsigned = UnsignedTx {
+            program: program("<k> nonce signtx drop", &[("k", key(1))]),
+            ..unsigned
+        };
+        let error = VM::dry_run(&unsigned).err().unwrap();
+        assert_eq!(error.error, VMError::StackUnderflow);
+        assert_eq!(error.offset, Some(39));
+    }
+
+    #[test]
+    fn combined_key_spend() {
+        let privkeys = vec![Scalar::from(5u64), Scalar::from(6u64)];
+        let pubkeys = privkeys
+            .iter()
+            .map(VerificationKey::from_secret)
+            .collect::<Vec<_>>();
+        let combined = VerificationKey::aggregate(&pubkeys).unwrap();
+        let prog = program(
+            "<k> nonce signtx 0x01 <combined> output:1",
+            &[("k", key(1)), ("combined", combined.0.to_bytes().to_vec())],
+        );
+        let parent = make_tx(prog, 0, 100, &[1]);
+
+        // Holders of the keys sign the spending transaction separately.
+        let prog = program("<input> input signtx drop", &[("input", spend(&parent, 0))]);
+        let tx = make_tx_with(prog, 0, 100, |transcript| {
+            let mut signatures = musig::tests::sign(
+                &privkeys,
+                |p, keys| Party::new_aggregated(transcript.clone(), p, keys),
+                |_| {},
+            );
+            signatures.remove(0).unwrap()
+        });
+        assert!(VM::verify_tx(&tx).is_ok());
+    }
+
+    #[test]
+    fn parallel_batch_unsupported_instruction() {
+        let mut txs = (1..5).map(|k| nonce_tx(k, k)).collect::<Vec<_>>();
+        txs[2].program = vec![Opcode::Call.to_u8()];
+        let error = VM::verify_txs_parallel(&txs).err().unwrap();
+        assert_eq!(error.index, 2);
+        assert_eq!(error.error.error, VMError::UnsupportedInstruction);
+        assert_eq!(error.error.instruction, Some(Instruction::Call));
+    }
+}